@@ -0,0 +1,109 @@
+use projekt::{
+    arguments::parse_bot_runner,
+    external_bot::{play_match, ExternalBot, MatchSettings},
+    rules::Side,
+};
+
+fn spawn(command: &str) -> ExternalBot {
+    ExternalBot::spawn(command).unwrap_or_else(|e| {
+        eprintln!("{}: {}", command, e);
+        std::process::exit(1);
+    })
+}
+
+fn main() {
+    let (args, config) = parse_bot_runner();
+    let mut bots = [spawn(&args.first), spawn(&args.second)];
+    let names = ["first", "second"];
+    let settings = MatchSettings {
+        budget: args.budget,
+        startup_timeout: args.startup_timeout,
+        max_ticks: Some(args.max_ticks),
+    };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    if !args.json {
+        println!("Seed {}", seed);
+    }
+
+    // wins of the first and second bot
+    let mut wins = [0, 0];
+    for number in 0..args.matches {
+        // bots change sides after every match
+        let left = (number % 2) as usize;
+        let right = 1 - left;
+        let [first, second] = &mut bots;
+        let (left_bot, right_bot) = if left == 0 {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        let report = play_match(
+            &config,
+            left_bot,
+            right_bot,
+            seed.wrapping_add(number as u64),
+            &settings,
+        );
+        let winner = report.winner.map(|side| match side {
+            Side::Left => left,
+            Side::Right => right,
+        });
+        if let Some(winner) = winner {
+            wins[winner] += 1;
+        }
+
+        if args.json {
+            let line = serde_json::json!({
+                "match": number + 1,
+                "left": names[left],
+                "right": names[right],
+                "winner": winner.map(|w| names[w]),
+                "report": report,
+            });
+            println!("{}", line);
+            continue;
+        }
+        println!(
+            "Match {}: {} {} : {} {}, {} in {} ticks",
+            number + 1,
+            names[left],
+            report.score.0,
+            report.score.1,
+            names[right],
+            winner.map_or("no winner".to_string(), |w| format!("{} wins", names[w])),
+            report.ticks
+        );
+        if let Some(forfeit) = &report.forfeit {
+            let loser = if forfeit.side == Side::Left {
+                left
+            } else {
+                right
+            };
+            println!("  {} forfeited: {}", names[loser], forfeit.reason);
+        }
+        for (side, timeouts, invalid) in [
+            (left, report.timeouts.0, report.invalid.0),
+            (right, report.timeouts.1, report.invalid.1),
+        ] {
+            if timeouts > 0 || invalid > 0 {
+                println!(
+                    "  {}: {} ticks without an answer in time, {} invalid answers",
+                    names[side], timeouts, invalid
+                );
+            }
+        }
+    }
+
+    let undecided = args.matches - wins[0] - wins[1];
+    if args.json {
+        let line = serde_json::json!({
+            "summary": { "first": wins[0], "second": wins[1], "undecided": undecided },
+        });
+        println!("{}", line);
+    } else {
+        println!(
+            "First ({}) won {}, second ({}) won {}, {} undecided",
+            args.first, wins[0], args.second, wins[1], undecided
+        );
+    }
+}
@@ -1,24 +1,47 @@
 use ggez::event;
 use projekt::{
     arguments::parse_player,
-    common::messages::{get_message, send_message, Message, PlayerId, ReadType, Side},
-    configuration::Configuration,
-    messages::wait_for_message,
+    auth::{hash_password, set_signing_key},
+    codec::PROTOCOL_VERSION,
+    common::messages::{
+        get_message, send_message, GameListing, Message, PlayerId, PlayerName, ReadType,
+    },
+    messages::{wait_for_ready, ReliableChannel, SessionToken, HEARTBEAT_INTERVAL},
     paddle_like::RectangularPaddle,
-    player_state::PlayerState,
+    player_state::{PlayerState, Session, ViewOptions},
 };
 use std::{
     net::{SocketAddr, UdpSocket},
     str::FromStr,
 };
 
-fn connect_to_server(socket: &UdpSocket, server: &SocketAddr) -> (Side, PlayerId) {
+// sends the join request until the server lets the player in
+// Socket should have a read timeout, the request is repeated after it
+fn connect_to_server(
+    socket: &UdpSocket,
+    channel: &ReliableChannel,
+    server: &SocketAddr,
+    join: Message,
+) -> (PlayerId, SessionToken) {
     println!("Trying to connect to server");
-    let join = Message::Join;
+    let mut seat = None;
     loop {
+        if let Some(seat) = seat {
+            // creator needs the code of the room too
+            if !matches!(join, Message::CreateRoom(_)) {
+                return seat;
+            }
+        }
         send_message(socket, &join, server);
-        let (msg, who) = match get_message(socket) {
+        let (msg, who) = match get_message(socket, channel) {
             ReadType::MessageRead(msg, who) => (msg, who),
+            ReadType::VersionMismatch(version, who) if who == *server => {
+                eprintln!(
+                    "Server speaks protocol version {}, but this player speaks version {}, update the one that's older",
+                    version, PROTOCOL_VERSION
+                );
+                std::process::exit(1);
+            }
             _ => continue,
         };
 
@@ -28,12 +51,22 @@ fn connect_to_server(socket: &UdpSocket, server: &SocketAddr) -> (Side, PlayerId
         }
 
         match msg {
-            Message::Ok(side, player_id) => {
-                println!("Connected to server as {:?} player", side);
-                return (side, player_id);
+            Message::Ok(player_id, token) => {
+                println!("Connected to server as player {}", player_id);
+                seat = Some((player_id, token));
             }
-            Message::Taken => {
-                panic!("Game is full");
+            Message::InRoom(code) => {
+                if let (Some(seat), Message::CreateRoom(_)) = (seat, join) {
+                    println!(
+                        "Room code: {}, your opponent joins with --room {}",
+                        code, code
+                    );
+                    return seat;
+                }
+            }
+            Message::Refused(refusal) => {
+                eprintln!("Server refused to let us in: {}", refusal);
+                std::process::exit(1);
             }
             _ => {
                 println!("wtf! Unexpected message: {:?}", msg);
@@ -42,32 +75,137 @@ fn connect_to_server(socket: &UdpSocket, server: &SocketAddr) -> (Side, PlayerId
     }
 }
 
+// requests without a useful answer after which the player gives up listing games
+const LIST_ATTEMPTS: u32 = 10;
+
+// asks for the games which can be watched, page after page, until a whole list comes
+// Socket should have a read timeout, the request is repeated after it
+fn list_games(socket: &UdpSocket, channel: &ReliableChannel, server: &SocketAddr) {
+    let mut listings: Vec<GameListing> = Vec::new();
+    // total of the pages so far, the list is asked for anew if it changes
+    let mut expected = None;
+    let mut attempts = 0;
+    let mut ask = true;
+    loop {
+        if ask {
+            if attempts == LIST_ATTEMPTS {
+                eprintln!("Server didn't send the list of games, giving up");
+                std::process::exit(1);
+            }
+            attempts += 1;
+            send_message(socket, &Message::ListGames(listings.len() as u32), server);
+            ask = false;
+        }
+        let (msg, who) = match get_message(socket, channel) {
+            ReadType::MessageRead(msg, who) => (msg, who),
+            _ => {
+                // timed out
+                ask = true;
+                continue;
+            }
+        };
+        if who != *server {
+            continue;
+        }
+        // pages answering an earlier request may come late or twice
+        let Message::GamesPage { from, games, total } = msg else {
+            continue;
+        };
+        if from as usize != listings.len() {
+            continue;
+        }
+        ask = true;
+        if expected.is_some_and(|expected| expected != total) {
+            // the list changed in the meantime
+            listings.clear();
+            expected = None;
+            continue;
+        }
+        expected = Some(total);
+        let before = listings.len();
+        listings.extend(games.iter().flatten());
+        if listings.len() >= total as usize {
+            break;
+        }
+        if listings.len() > before {
+            attempts = 0;
+        }
+    }
+    if listings.is_empty() {
+        println!("No games to watch");
+    }
+    let name = |name: Option<PlayerName>| name.map_or("guest".to_string(), |name| name.to_string());
+    for listing in listings {
+        println!(
+            "game {}: {} vs {}, {} watching, run with --spectate {}",
+            listing.game_id,
+            name(listing.left),
+            name(listing.right),
+            listing.spectators,
+            listing.game_id
+        );
+    }
+}
+
 fn main() -> ggez::GameResult {
-    let (ip, port, server_ip, server_port) = parse_player();
+    let (args, config) = parse_player();
 
-    let server_address_string: String = format!("{}:{}", server_ip, server_port);
+    let server_address_string: String = format!("{}:{}", args.server_ip, args.server_port);
     let server_address =
         SocketAddr::from_str(&server_address_string).expect("Couldn't parse server address");
     println!("Connecting to server at {}", server_address);
 
     // Bind the socket to an address and port
-    let socket = UdpSocket::bind(format!("{}:{}", ip, port)).expect("couldn't bind to address");
+    let socket =
+        UdpSocket::bind(format!("{}:{}", args.ip, args.port)).expect("couldn't bind to address");
     let local_addr = socket.local_addr().expect("Couldn't get local address");
     println!("Binded on {}:{}", local_addr.ip(), local_addr.port());
 
     socket
-        .set_read_timeout(None)
+        .set_read_timeout(Some(HEARTBEAT_INTERVAL))
         .expect("set_read_timeout call failed");
-    let (side, player_id) = connect_to_server(&socket, &server_address);
+    let channel = ReliableChannel::new();
+    if args.list_games {
+        list_games(&socket, &channel, &server_address);
+        return Ok(());
+    }
+    let password = args.password.as_deref().map(hash_password);
+    let join = match (args.rejoin, args.room, args.spectate) {
+        (Some(token), _, _) => Message::Rejoin(token),
+        (None, Some(code), _) => Message::JoinRoom(code, password),
+        (None, None, Some(game_id)) => Message::Spectate(game_id),
+        (None, None, None) if args.create_room => Message::CreateRoom(password),
+        (None, None, None) => {
+            Message::QuickMatch(args.name.zip(args.secret.as_deref().map(hash_password)))
+        }
+    };
+    let (player_id, token) = connect_to_server(&socket, &channel, &server_address, join);
+    set_signing_key(player_id, token);
+    println!(
+        "Session token: {}, if the player crashes run it again with --rejoin {} to get back to the game",
+        token, token
+    );
 
     println!("Waiting for game to start");
-    wait_for_message(&socket, &server_address, Message::Ready);
+    let Some((side, server_config)) = wait_for_ready(&socket, &channel, &server_address, player_id)
+    else {
+        eprintln!("Server stopped responding");
+        std::process::exit(1);
+    };
+    match side {
+        Some(side) => println!("Playing on the {:?} side", side),
+        None => println!("Watching the game"),
+    }
+    if let Err(e) = server_config.validate() {
+        eprintln!("Server sent {}", e);
+        std::process::exit(1);
+    }
+    let config = config.with_gameplay_of(&server_config);
 
     socket
         .set_nonblocking(true)
         .expect("set_nonblocking call failed");
 
-    let config = Configuration::default();
     let screen_width = config.screen_width;
     let screen_height = config.screen_height;
     let (mut ctx, event_loop) = ggez::ContextBuilder::new("multiplayer_pong", "marcin g")
@@ -77,10 +215,18 @@ fn main() -> ggez::GameResult {
     let state = PlayerState::<RectangularPaddle, RectangularPaddle>::new(
         config,
         &mut ctx,
-        side,
+        Session {
+            side,
+            player_id,
+            token,
+        },
         socket,
+        channel,
         server_address,
-        player_id,
+        ViewOptions {
+            interpolation_delay: args.interpolation_delay,
+            mirror: args.mirror,
+        },
     );
     // let mut c = conf::Conf::new();
     // c.window_mode(ggez::conf::WindowMode::default().dimensions(800.0, 600.0));
@@ -0,0 +1,30 @@
+use ggez::event;
+use projekt::{
+    arguments::parse_replay, paddle_like::RectangularPaddle, replay::Replay,
+    replay_viewer::ReplayViewer,
+};
+
+fn main() -> ggez::GameResult {
+    let args = parse_replay();
+    let replay = Replay::load(&args.file).unwrap_or_else(|e| {
+        eprintln!("Error in {}: {}", args.file.display(), e);
+        std::process::exit(1);
+    });
+    let timeline = replay.simulate();
+    // still worth watching, but it isn't exactly what happened
+    if let Err(e) = replay.check(&timeline) {
+        eprintln!("Warning: {}", e);
+    }
+
+    let config = replay.header.config;
+    let (mut ctx, event_loop) = ggez::ContextBuilder::new("multiplayer_pong", "marcin g")
+        .window_setup(ggez::conf::WindowSetup::default().title("Pong replay"))
+        .window_mode(
+            ggez::conf::WindowMode::default().dimensions(config.screen_width, config.screen_height),
+        )
+        .build()?;
+    let state = ReplayViewer::<RectangularPaddle, RectangularPaddle>::new(
+        &replay, timeline, &mut ctx, args.tick,
+    );
+    event::run(ctx, event_loop, state);
+}
@@ -1,20 +1,42 @@
 use projekt::{
     arguments::parse_server,
-    common::messages::{get_message, Direction, Message, PlayerMove, ReadType, Side, Tick},
+    auth::PasswordHash,
+    codec::PROTOCOL_VERSION,
+    common::messages::{get_message, Message, Pause, PlayerMove, ReadType, Side, Tick},
     configuration::{Configuration, FromConfiguration},
-    messages::{send_message, send_safely, PlayerId},
+    controller::{Controller, Difficulty, PredictiveAi, Situation},
+    matchmaking::MatchmakingQueue,
+    messages::{
+        send_message, GameId, GameListing, PlayerId, PlayerName, Refusal, ReliableChannel,
+        RoomCode, SessionToken, CONNECTION_LOST_AFTER, GAMES_PER_PAGE, HEARTBEAT_INTERVAL,
+        RETRANSMIT_INTERVAL,
+    },
     multiplayer_pong::MultiplayerPong,
+    rating::{Ratings, DEFAULT_RATING},
+    replay::{ReplayHeader, ReplayPlayer, ReplayWriter},
+    rules::Match,
     state::RoundResult,
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     net::{SocketAddr, UdpSocket},
-    sync::mpsc,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
     thread::{self, JoinHandle},
-    time::Instant,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-const TICKSPERSECOND: u64 = 30;
+// after everyone is back, players get that long to get ready before the game goes on
+const RESUME_COUNTDOWN: Duration = Duration::from_secs(3);
+// stands for the bot in replays, players' ids are counted from 0 so they never get that far
+const BOT_PLAYER_ID: PlayerId = PlayerId::MAX;
+
+// when the server seats a bot against a player left waiting in the queue
+#[derive(Debug, Clone, Copy)]
+struct Bots {
+    after: Duration,
+    difficulty: Difficulty,
+}
 
 #[derive(Default)]
 pub struct PlayerInput {
@@ -27,10 +49,8 @@ impl PlayerInput {
     fn update(&mut self, player_move: PlayerMove) {
         if player_move.tick > self.tick {
             self.tick = player_move.tick;
-            match player_move.dir {
-                Direction::Up(b) => self.up = b,
-                Direction::Down(b) => self.down = b,
-            }
+            self.up = player_move.up;
+            self.down = player_move.down;
         }
     }
 }
@@ -54,26 +74,124 @@ impl PlayerInput {
 // }
 
 fn main() {
-    let (ip, port) = parse_server();
+    let (args, config) = parse_server();
 
     // Bind the socket to an address and port
-    let socket = UdpSocket::bind(format!("{}:{}", ip, port)).expect("couldn't bind to address");
+    let socket =
+        UdpSocket::bind(format!("{}:{}", args.ip, args.port)).expect("couldn't bind to address");
 
     let local_addr = socket.local_addr().expect("Couldn't get local address");
     println!("Listening on {}:{}", local_addr.ip(), local_addr.port());
 
+    let ratings = Ratings::load(&args.ratings).unwrap_or_else(|e| {
+        eprintln!("Error in {}: {}", args.ratings.display(), e);
+        std::process::exit(1);
+    });
+    let rating_book = Arc::new(RatingBook {
+        ratings: Mutex::new(ratings),
+        path: args.ratings.clone(),
+    });
+
+    if let Some(dir) = &args.replays {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Couldn't create {}: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    }
+
     let (sender, receiver) = mpsc::channel();
-    thread::spawn(|| thread_starter(receiver));
-    server(&socket, sender);
+    let book = rating_book.clone();
+    thread::spawn(move || thread_starter(receiver, &book));
+    let channel = Arc::new(ReliableChannel::new());
+    let launcher = GameLauncher {
+        socket: socket.try_clone().unwrap(),
+        channel: channel.clone(),
+        to_game_starter: sender,
+        config,
+        grace_period: args.player_timeout.saturating_sub(CONNECTION_LOST_AFTER),
+        replays: args.replays,
+    };
+    server(
+        &socket,
+        channel,
+        launcher,
+        args.player_timeout,
+        &rating_book,
+        args.bot_after.map(|after| Bots {
+            after,
+            difficulty: args.bot_difficulty,
+        }),
+    );
+}
+
+// ratings of named players, shared by the main thread (matchmaking)
+// and the thread starter (results of finished games)
+struct RatingBook {
+    ratings: Mutex<Ratings>,
+    // saved there after every rated game
+    path: PathBuf,
+}
+
+impl RatingBook {
+    fn rating_of(&self, name: Option<PlayerName>) -> f64 {
+        match name {
+            Some(name) => self.ratings.lock().unwrap().get(name.as_str()).rating,
+            None => DEFAULT_RATING,
+        }
+    }
+
+    // whether the player may play under the name, see Ratings::claim
+    fn claim(&self, name: PlayerName, secret: &PasswordHash) -> bool {
+        let mut ratings = self.ratings.lock().unwrap();
+        let allowed = ratings.claim(name.as_str(), secret);
+        // a new name is kept right away
+        if allowed {
+            if let Err(e) = ratings.save(&self.path) {
+                eprintln!("Error in {}: {}", self.path.display(), e);
+            }
+        }
+        allowed
+    }
+
+    fn record(&self, left: PlayerName, right: PlayerName, winner: Side) {
+        let mut ratings = self.ratings.lock().unwrap();
+        ratings.record(left.as_str(), right.as_str(), winner == Side::Left);
+        println!(
+            "Rated game won by {:?}, {} is now {:.0}, {} is now {:.0}",
+            winner,
+            left,
+            ratings.get(left.as_str()).rating,
+            right,
+            ratings.get(right.as_str()).rating
+        );
+        if let Err(e) = ratings.save(&self.path) {
+            eprintln!("Error in {}: {}", self.path.display(), e);
+        }
+    }
 }
 
-pub fn send_by_pipe(send: &mpsc::Sender<Message>, msg: Message) {
+// what the main thread tells a game thread
+#[derive(Debug)]
+enum GameEvent {
+    Move(PlayerMove),
+    // player left or timed out, game ends
+    Ended(PlayerId),
+    // player stopped responding, game waits for it
+    Disconnected(PlayerId),
+    // player is back, possibly from another address
+    Reconnected(PlayerId, SocketAddr),
+    // spectator starts watching, or is back at a new address
+    Watch(PlayerId, SocketAddr),
+    Unwatch(PlayerId),
+}
+
+fn send_by_pipe(send: &mpsc::Sender<GameEvent>, msg: GameEvent) {
     if let Err(e) = send.send(msg) {
         println!("Error sending message: {:?}", e);
     }
 }
 
-pub fn recv_from_pipe(recv: &mpsc::Receiver<Message>) -> Option<Message> {
+fn recv_from_pipe(recv: &mpsc::Receiver<GameEvent>) -> Option<GameEvent> {
     match recv.try_recv() {
         Ok(msg) => Some(msg),
         Err(mpsc::TryRecvError::Empty) => None,
@@ -83,52 +201,181 @@ pub fn recv_from_pipe(recv: &mpsc::Receiver<Message>) -> Option<Message> {
     }
 }
 
-fn single_game_thread(game: GameStarter) {
+// whole seconds left until the moment, for countdowns shown to players
+fn seconds_until(moment: Instant) -> u32 {
+    moment
+        .saturating_duration_since(Instant::now())
+        .as_secs_f32()
+        .ceil() as u32
+}
+
+// score, followed by the result if the match is decided
+fn send_result(socket: &UdpSocket, channel: &ReliableChannel, game: &Match, addr: &SocketAddr) {
+    let score = game.score;
+    channel.send(socket, &Message::Score(score.0, score.1), addr);
+    if let Some(winner) = game.winner() {
+        channel.send(socket, &Message::MatchOver { winner, score }, addr);
+    }
+}
+
+// Plays the game until a player leaves, a decided match stands still until then.
+// Returns the winner: of the match if it was decided, otherwise whoever didn't leave.
+fn single_game_thread(game: GameStarter) -> Option<Side> {
     let GameStarter {
+        game_id,
         msg_recv,
-        left_player_addr: player_left_addr,
-        right_player_addr: player_right_addr,
+        left_player_addr: mut player_left_addr,
         left_player_id,
-        right_player_id,
+        right,
         socket,
+        channel,
+        config,
+        grace_period,
+        // the thread starter takes care of ratings
+        rated: _,
+        mut replay,
     } = game;
 
-    send_safely(&socket, &Message::Ready, &player_left_addr);
-    send_safely(&socket, &Message::Ready, &player_right_addr);
+    channel.send(
+        &socket,
+        &Message::Ready(Some(Side::Left), config),
+        &player_left_addr,
+    );
+    // the bot has no address, it plays right here
+    let (right_player_id, mut player_right_addr, mut bot) = match right {
+        Seat::Player(player_id, addr) => (Some(player_id), Some(addr), None),
+        Seat::Bot(difficulty) => (
+            None,
+            None,
+            Some(PredictiveAi::new(difficulty, rand::random())),
+        ),
+    };
+    if let Some(addr) = &player_right_addr {
+        channel.send(&socket, &Message::Ready(Some(Side::Right), config), addr);
+    }
+    // get everything players get, but their input isn't taken
+    let mut spectators: Vec<(PlayerId, SocketAddr)> = Vec::new();
 
-    let mut multiplayer_pong = MultiplayerPong::from_configuration(&Configuration::default());
+    let mut multiplayer_pong = MultiplayerPong::from_configuration(&config);
 
-    let interval = std::time::Duration::from_millis(1000 / TICKSPERSECOND);
+    let interval = std::time::Duration::from_secs_f32(1.0 / config.ticks_per_second as f32);
     let mut left_last_move = PlayerInput::default();
     let mut right_last_move = PlayerInput::default();
-    let mut score = (0, 0);
 
-    let dt = 1.0 / TICKSPERSECOND as f32;
+    let dt = 1.0 / config.ticks_per_second as f32;
     let mut tick = 0;
 
+    // players who lost connection, with the moment the game is forfeited
+    let mut waiting_for: Vec<(PlayerId, Instant)> = Vec::new();
+    let mut resume_at: Option<Instant> = None;
+
     loop {
         let start = Instant::now();
 
         while let Some(message) = recv_from_pipe(&msg_recv) {
             match message {
-                Message::EndingGame(player_id) => {
-                    send_safely(&socket, &Message::EndingGame(player_id), &player_left_addr);
-                    send_safely(&socket, &Message::EndingGame(player_id), &player_right_addr);
-                    return;
+                GameEvent::Ended(player_id) => {
+                    let score = multiplayer_pong.game.score;
+                    if let Some(replay) = replay.take() {
+                        if let Err(e) = replay.finish(score) {
+                            eprintln!("Couldn't finish the replay of game {}: {}", game_id, e);
+                        }
+                    }
+                    // delivered by the main thread's retransmissions after this thread ends
+                    channel.send(&socket, &Message::EndingGame(player_id), &player_left_addr);
+                    if let Some(addr) = &player_right_addr {
+                        channel.send(&socket, &Message::EndingGame(player_id), addr);
+                    }
+                    for (_, addr) in &spectators {
+                        channel.send(&socket, &Message::EndingGame(player_id), addr);
+                    }
+                    let leaver = if player_id == left_player_id {
+                        Some(Side::Left)
+                    } else if Some(player_id) == right_player_id {
+                        Some(Side::Right)
+                    } else {
+                        None
+                    };
+                    return multiplayer_pong
+                        .game
+                        .winner()
+                        .or(leaver.map(Side::opposite));
                 }
-                Message::Move(player_move) => {
+                GameEvent::Move(player_move) => {
                     if player_move.player_id == left_player_id {
                         left_last_move.update(player_move);
-                    } else if player_move.player_id == right_player_id {
+                    } else if Some(player_move.player_id) == right_player_id {
                         right_last_move.update(player_move);
                     }
                 }
-                _ => {
-                    panic!("Imposiible")
+                GameEvent::Disconnected(player_id) => {
+                    println!("Game {} waits for player {}", game_id, player_id);
+                    waiting_for.push((player_id, Instant::now() + grace_period));
+                }
+                GameEvent::Reconnected(player_id, addr) => {
+                    println!("Player {} is back at {}", player_id, addr);
+                    // restarted player counts its input ticks from the beginning
+                    if player_id == left_player_id {
+                        player_left_addr = addr;
+                        left_last_move = PlayerInput::default();
+                    } else if Some(player_id) == right_player_id {
+                        player_right_addr = Some(addr);
+                        right_last_move = PlayerInput::default();
+                    }
+                    send_result(&socket, &channel, &multiplayer_pong.game, &addr);
+                    waiting_for.retain(|&(id, _)| id != player_id);
+                    if waiting_for.is_empty() {
+                        resume_at = Some(Instant::now() + RESUME_COUNTDOWN);
+                    }
+                }
+                GameEvent::Watch(player_id, addr) => {
+                    // spectator who rejoined from a new address is replaced
+                    spectators.retain(|&(id, _)| id != player_id);
+                    spectators.push((player_id, addr));
+                    channel.send(&socket, &Message::Ready(None, config), &addr);
+                    send_result(&socket, &channel, &multiplayer_pong.game, &addr);
+                }
+                GameEvent::Unwatch(player_id) => {
+                    spectators.retain(|&(id, _)| id != player_id);
                 }
             };
         }
 
+        // stopped game doesn't tick, players are told why instead
+        let pause = match (waiting_for.first(), resume_at) {
+            (Some(&(player_id, deadline)), _) => {
+                Some(Pause::WaitingFor(player_id, seconds_until(deadline)))
+            }
+            (None, Some(resume)) if resume > Instant::now() => {
+                Some(Pause::Resuming(seconds_until(resume)))
+            }
+            _ => None,
+        };
+        if let Some(pause) = pause {
+            send_message(&socket, &Message::Paused(pause), &player_left_addr);
+            if let Some(addr) = &player_right_addr {
+                send_message(&socket, &Message::Paused(pause), addr);
+            }
+            for (_, addr) in &spectators {
+                send_message(&socket, &Message::Paused(pause), addr);
+            }
+            let elapsed = start.elapsed();
+            if elapsed < interval {
+                thread::sleep(interval - elapsed);
+            }
+            continue;
+        }
+
+        // bot decides on what it sees, as a player would
+        if let Some(bot) = &mut bot {
+            let (up, down) = bot.keys(&Situation::new(
+                &multiplayer_pong.ball,
+                &multiplayer_pong.right_paddle,
+                dt,
+            ));
+            right_last_move = PlayerInput { tick, up, down };
+        }
+
         // Update game state
         let (round_result, game_state) = multiplayer_pong.multi_game_round(
             (left_last_move.up, left_last_move.down),
@@ -136,40 +383,46 @@ fn single_game_thread(game: GameStarter) {
             dt,
             tick,
         );
+        if let Err(e) = replay.as_mut().map_or(Ok(()), |replay| {
+            replay.record_tick(
+                (left_last_move.up, left_last_move.down),
+                (right_last_move.up, right_last_move.down),
+                tick,
+                &multiplayer_pong,
+            )
+        }) {
+            eprintln!("Stopped recording game {}: {}", game_id, e);
+            replay = None;
+        }
+        // so that players know which of their inputs are already included
+        let game_state = game_state.with_input_ticks(left_last_move.tick, right_last_move.tick);
 
-        // Send game state to players
-        send_message(&socket, &Message::State(game_state), &player_left_addr);
-        send_message(&socket, &Message::State(game_state), &player_right_addr);
-
-        // Send score to players
-        match round_result {
-            RoundResult::LeftScored => {
-                score.0 += 1;
-                send_message(
-                    &socket,
-                    &Message::Score(score.0, score.1),
-                    &player_left_addr,
-                );
-                send_message(
-                    &socket,
-                    &Message::Score(score.0, score.1),
-                    &player_right_addr,
-                );
+        // Send game state to players and spectators
+        let audience: Vec<SocketAddr> = std::iter::once(player_left_addr)
+            .chain(player_right_addr)
+            .chain(spectators.iter().map(|&(_, addr)| addr))
+            .collect();
+        for addr in &audience {
+            send_message(&socket, &Message::State(game_state), addr);
+        }
+
+        // Send score to everyone, and the result if that point decided the match
+        if !matches!(round_result, RoundResult::None) {
+            for addr in &audience {
+                send_result(&socket, &channel, &multiplayer_pong.game, addr);
             }
-            RoundResult::RightScored => {
-                score.1 += 1;
-                send_message(
-                    &socket,
-                    &Message::Score(score.0, score.1),
-                    &player_left_addr,
-                );
-                send_message(
-                    &socket,
-                    &Message::Score(score.0, score.1),
-                    &player_right_addr,
+            if let Some(winner) = multiplayer_pong.game.winner() {
+                let score = multiplayer_pong.game.score;
+                println!(
+                    "Game {} is over, {:?} won {}:{}",
+                    game_id, winner, score.0, score.1
                 );
+                if let Some(replay) = replay.take() {
+                    if let Err(e) = replay.finish(score) {
+                        eprintln!("Couldn't finish the replay of game {}: {}", game_id, e);
+                    }
+                }
             }
-            RoundResult::None => {}
         }
 
         tick += 1;
@@ -182,52 +435,73 @@ fn single_game_thread(game: GameStarter) {
     }
 }
 
-type GameId = u32;
+// right side of a starting game
+enum Seat {
+    Player(PlayerId, SocketAddr),
+    // played by the server itself
+    Bot(Difficulty),
+}
 
 struct GameStarter {
-    msg_recv: mpsc::Receiver<Message>,
+    game_id: GameId,
+    msg_recv: mpsc::Receiver<GameEvent>,
     left_player_addr: SocketAddr,
-    right_player_addr: SocketAddr,
     left_player_id: PlayerId,
-    right_player_id: PlayerId,
+    right: Seat,
     socket: UdpSocket,
-}
-
-impl GameStarter {
-    pub fn game_id(&self) -> GameId {
-        get_game_id(self.left_player_id)
-    }
+    channel: Arc<ReliableChannel>,
+    config: Configuration,
+    // how long the game waits for a player who lost connection
+    grace_period: Duration,
+    // names of (left, right) if the result counts for their ratings
+    rated: Option<(PlayerName, PlayerName)>,
+    // records the game, if the server keeps replays
+    replay: Option<ReplayWriter>,
 }
 
 enum InterThreadMessage {
-    StartGame(GameStarter),
+    StartGame(Box<GameStarter>),
     EndGame(GameId),
 }
 
 // separate thread for starting thread cos it possibly takes long and we don't want to block other games
-fn thread_starter(recv: mpsc::Receiver<InterThreadMessage>) {
-    let mut games: HashMap<GameId, JoinHandle<()>> = HashMap::new();
+// Finished rated games update the ratings.
+fn thread_starter(recv: mpsc::Receiver<InterThreadMessage>, rating_book: &RatingBook) {
+    type RatedNames = Option<(PlayerName, PlayerName)>;
+    let mut games: HashMap<GameId, (JoinHandle<Option<Side>>, RatedNames)> = HashMap::new();
     loop {
         match recv.recv() {
             Ok(msg) => match msg {
                 InterThreadMessage::StartGame(game_starter) => {
                     println!("starting game");
-                    println!("adding {}", game_starter.game_id());
+                    println!("adding {}", game_starter.game_id);
+                    let game_id = game_starter.game_id;
+                    let rated = game_starter.rated;
                     games.insert(
-                        game_starter.game_id(),
-                        thread::spawn(move || {
-                            single_game_thread(game_starter);
-                        }),
+                        game_id,
+                        (
+                            thread::spawn(move || single_game_thread(*game_starter)),
+                            rated,
+                        ),
                     );
                 }
                 InterThreadMessage::EndGame(game_id) => {
-                    println!("removing game: {:?}", games.get(&game_id));
-                    // let game = games.remove(&game_id).unwrap();
-                    // if let Err(e) = game.join() {
-                    //     // don't want to panic here
-                    //     // so that server remains usable
-                    //     println!("Game thread panicked: {:?}", e);
-                    // }
+                    println!("removing game: {:?}", game_id);
+                    if let Some((game, rated)) = games.remove(&game_id) {
+                        // game thread ends within a tick after getting EndingGame
+                        match game.join() {
+                            Ok(winner) => {
+                                if let (Some((left, right)), Some(winner)) = (rated, winner) {
+                                    rating_book.record(left, right, winner);
+                                }
+                            }
+                            Err(e) => {
+                                // don't want to panic here
+                                // so that server remains usable
+                                println!("Game thread panicked: {:?}", e);
+                            }
+                        }
+                    }
                 }
             },
             Err(_) => {
@@ -237,109 +511,850 @@ fn thread_starter(recv: mpsc::Receiver<InterThreadMessage>) {
     }
 }
 
-//
-pub fn get_game_id(player_id: PlayerId) -> GameId {
-    player_id / 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameStatus {
+    // room has its first player, waits for the second one
+    Waiting,
+    Running,
+    Finished,
 }
 
-pub struct Players {
-    players_addr: HashMap<PlayerId, SocketAddr>,
-    pub game_thread_communication: HashMap<GameId, mpsc::Sender<Message>>,
+// private game, joined by code
+struct Room {
+    code: RoomCode,
+    password: Option<PasswordHash>,
+}
+
+// who plays against the player on the left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opponent {
+    Player(PlayerId),
+    // server's AI, seated when nobody came for too long
+    Bot(Difficulty),
+}
+
+struct GameEntry {
+    left: PlayerId,
+    right: Option<Opponent>,
+    status: GameStatus,
+    // None for games paired by matchmaking
+    room: Option<Room>,
+    // to the game thread, while it runs
+    sender: Option<mpsc::Sender<GameEvent>>,
+    spectators: Vec<PlayerId>,
 }
 
+// finished games are remembered for a while, then forgotten
+const FINISHED_GAMES_KEPT: usize = 1000;
 
-impl Players {
+// Every game the server knows of, from the moment its first player sits down.
+// Game ids are never reused.
+pub struct GameRegistry {
+    games: HashMap<GameId, GameEntry>,
+    next_game_id: GameId,
+    // oldest first
+    finished: VecDeque<GameId>,
+    // codes of rooms which haven't finished yet
+    rooms: HashMap<RoomCode, GameId>,
+}
+
+impl Default for GameRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GameRegistry {
     pub fn new() -> Self {
         Self {
-            players_addr: HashMap::new(),
-            game_thread_communication: HashMap::new(),
+            games: HashMap::new(),
+            next_game_id: 0,
+            finished: VecDeque::new(),
+            rooms: HashMap::new(),
+        }
+    }
+
+    pub fn find_room(
+        &self,
+        code: RoomCode,
+        password: Option<PasswordHash>,
+    ) -> Result<GameId, Refusal> {
+        let &game_id = self.rooms.get(&code).ok_or(Refusal::NoSuchRoom)?;
+        let game = &self.games[&game_id];
+        if game
+            .room
+            .as_ref()
+            .unwrap()
+            .password
+            .is_some_and(|p| Some(p) != password)
+        {
+            return Err(Refusal::WrongPassword);
+        }
+        if game.status != GameStatus::Waiting {
+            return Err(Refusal::RoomFull);
+        }
+        Ok(game_id)
+    }
+
+    // game of two players paired by matchmaking
+    pub fn create_match(&mut self, left: PlayerId, right: PlayerId) -> GameId {
+        self.insert(left, Some(Opponent::Player(right)), None)
+    }
+
+    // game of a player nobody was found for, against the server's bot
+    pub fn create_bot_match(&mut self, left: PlayerId, difficulty: Difficulty) -> GameId {
+        self.insert(left, Some(Opponent::Bot(difficulty)), None)
+    }
+
+    pub fn create_room(
+        &mut self,
+        player_id: PlayerId,
+        password: Option<PasswordHash>,
+    ) -> (GameId, RoomCode) {
+        let code = loop {
+            let code = RoomCode::random();
+            if !self.rooms.contains_key(&code) {
+                break code;
+            }
+        };
+        let game_id = self.insert(player_id, None, Some(Room { code, password }));
+        self.rooms.insert(code, game_id);
+        (game_id, code)
+    }
+
+    fn insert(&mut self, left: PlayerId, right: Option<Opponent>, room: Option<Room>) -> GameId {
+        let game_id = self.next_game_id;
+        self.next_game_id += 1;
+        self.games.insert(
+            game_id,
+            GameEntry {
+                left,
+                right,
+                status: GameStatus::Waiting,
+                room,
+                sender: None,
+                spectators: Vec::new(),
+            },
+        );
+        game_id
+    }
+
+    // the opponent takes the right side of a waiting game
+    pub fn sit_right(&mut self, game_id: GameId, player_id: PlayerId) {
+        if let Some(game) = self.games.get_mut(&game_id) {
+            game.right = Some(Opponent::Player(player_id));
+        }
+    }
+
+    pub fn room_code(&self, game_id: GameId) -> Option<RoomCode> {
+        Some(self.games.get(&game_id)?.room.as_ref()?.code)
+    }
+
+    // (left, right), once both are there
+    pub fn opponents(&self, game_id: GameId) -> Option<(PlayerId, Opponent)> {
+        let game = self.games.get(&game_id)?;
+        Some((game.left, game.right?))
+    }
+
+    pub fn players(&self, game_id: GameId) -> Vec<PlayerId> {
+        match self.games.get(&game_id) {
+            Some(game) => {
+                let right = match game.right {
+                    Some(Opponent::Player(player_id)) => Some(player_id),
+                    _ => None,
+                };
+                std::iter::once(game.left).chain(right).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    pub fn spectators(&self, game_id: GameId) -> Vec<PlayerId> {
+        match self.games.get(&game_id) {
+            Some(game) => game.spectators.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    // running games which aren't private
+    pub fn is_watchable(&self, game_id: GameId) -> bool {
+        self.games
+            .get(&game_id)
+            .is_some_and(|game| game.status == GameStatus::Running && game.room.is_none())
+    }
+
+    // oldest first
+    pub fn watchable(&self) -> Vec<GameId> {
+        let mut watchable: Vec<GameId> = self
+            .games
+            .keys()
+            .copied()
+            .filter(|&game_id| self.is_watchable(game_id))
+            .collect();
+        watchable.sort();
+        watchable
+    }
+
+    pub fn watch(&mut self, game_id: GameId, player_id: PlayerId) {
+        if let Some(game) = self.games.get_mut(&game_id) {
+            game.spectators.push(player_id);
+        }
+    }
+
+    pub fn unwatch(&mut self, game_id: GameId, player_id: PlayerId) {
+        if let Some(game) = self.games.get_mut(&game_id) {
+            game.spectators.retain(|&id| id != player_id);
+        }
+    }
+
+    pub fn status(&self, game_id: GameId) -> Option<GameStatus> {
+        self.games.get(&game_id).map(|game| game.status)
+    }
+
+    fn start(&mut self, game_id: GameId, sender: mpsc::Sender<GameEvent>) {
+        if let Some(game) = self.games.get_mut(&game_id) {
+            game.status = GameStatus::Running;
+            game.sender = Some(sender);
+        }
+    }
+
+    // returns the status the game had before
+    pub fn finish(&mut self, game_id: GameId) -> Option<GameStatus> {
+        let game = self.games.get_mut(&game_id)?;
+        let status = game.status;
+        if status != GameStatus::Finished {
+            game.status = GameStatus::Finished;
+            game.sender = None;
+            // code can be given to another room
+            if let Some(room) = &game.room {
+                self.rooms.remove(&room.code);
+            }
+            self.finished.push_back(game_id);
+            if self.finished.len() > FINISHED_GAMES_KEPT {
+                let oldest = self.finished.pop_front().unwrap();
+                self.games.remove(&oldest);
+            }
+        }
+        Some(status)
+    }
+
+    // only running games have a thread to tell
+    fn send(&self, game_id: GameId, event: GameEvent) {
+        match self.games.get(&game_id) {
+            Some(GameEntry {
+                sender: Some(sender),
+                ..
+            }) => send_by_pipe(sender, event),
+            Some(_) => {}
+            None => println!("Game {} not found for {:?}", game_id, event),
+        }
+    }
+}
+
+struct PlayerInfo {
+    addr: SocketAddr,
+    token: SessionToken,
+    // rating is kept under it, None for guests
+    name: Option<PlayerName>,
+    last_seen: Instant,
+    // false after the player went silent for CONNECTION_LOST_AFTER
+    connected: bool,
+    // game the player sits at and its side there, None while in the matchmaking queue
+    seat: Option<(GameId, Side)>,
+    // game the player only watches
+    watching: Option<GameId>,
+}
+
+pub struct Players {
+    players: HashMap<PlayerId, PlayerInfo>,
+    ids_by_addr: HashMap<SocketAddr, PlayerId>,
+    ids_by_token: HashMap<SessionToken, PlayerId>,
+    next_player_id: PlayerId,
+    // told about players who are gone
+    channel: Arc<ReliableChannel>,
+}
+
+impl Players {
+    pub fn new(channel: Arc<ReliableChannel>) -> Self {
+        Self {
+            players: HashMap::new(),
+            ids_by_addr: HashMap::new(),
+            ids_by_token: HashMap::new(),
+            next_player_id: 0,
+            channel,
         }
     }
 
     pub fn get_player_addr(&self, player_id: PlayerId) -> Option<&SocketAddr> {
-        self.players_addr.get(&player_id)
+        self.players.get(&player_id).map(|p| &p.addr)
+    }
+
+    pub fn get_player_id(&self, addr: &SocketAddr) -> Option<PlayerId> {
+        self.ids_by_addr.get(addr).copied()
+    }
+
+    pub fn get_player_by_token(&self, token: SessionToken) -> Option<PlayerId> {
+        self.ids_by_token.get(&token).copied()
+    }
+
+    pub fn side_of(&self, player_id: PlayerId) -> Option<Side> {
+        self.players.get(&player_id)?.seat.map(|(_, side)| side)
+    }
+
+    pub fn token_of(&self, player_id: PlayerId) -> Option<SessionToken> {
+        self.players.get(&player_id).map(|p| p.token)
+    }
+
+    pub fn game_of(&self, player_id: PlayerId) -> Option<GameId> {
+        self.players
+            .get(&player_id)?
+            .seat
+            .map(|(game_id, _)| game_id)
+    }
+
+    pub fn name_of(&self, player_id: PlayerId) -> Option<PlayerName> {
+        self.players.get(&player_id)?.name
+    }
+
+    // whether a player with the name is queued or playing
+    pub fn has_name(&self, name: PlayerName) -> bool {
+        self.players.values().any(|p| p.name == Some(name))
     }
 
     pub fn num_players(&self) -> usize {
-        self.players_addr.len()
+        self.players.len()
     }
 
-    pub fn add_player(&mut self, addr: SocketAddr) -> PlayerId {
-        let player_id = self.num_players() as PlayerId;
-        self.players_addr.insert(player_id, addr);
+    pub fn add_player(&mut self, addr: SocketAddr, name: Option<PlayerName>) -> PlayerId {
+        let player_id = self.next_player_id;
+        self.next_player_id += 1;
+        // tokens are how a player proves who it is when coming back, so they can't be guessable
+        let token = loop {
+            let token = rand::random();
+            if !self.ids_by_token.contains_key(&token) {
+                break token;
+            }
+        };
+        self.players.insert(
+            player_id,
+            PlayerInfo {
+                addr,
+                token,
+                name,
+                last_seen: Instant::now(),
+                connected: true,
+                seat: None,
+                watching: None,
+            },
+        );
+        self.ids_by_addr.insert(addr, player_id);
+        self.ids_by_token.insert(token, player_id);
+        self.channel.authenticate(&addr, player_id, token);
         player_id
     }
 
-    pub fn is_ready(&self) -> Option<(PlayerId, PlayerId)> {
-        let n = self.num_players() as PlayerId;
-        println!("num of players{}", n);
-        if n % 2 == 0 {
-            Some((n - 1, n - 2))
-        } else {
-            None
+    pub fn remove_player(&mut self, player_id: PlayerId) {
+        if let Some(player) = self.players.remove(&player_id) {
+            self.ids_by_addr.remove(&player.addr);
+            self.ids_by_token.remove(&player.token);
+            self.channel.close(&player.addr);
         }
     }
 
-    pub fn add_game(&mut self, game_id: GameId, msg_send: mpsc::Sender<Message>) {
-        self.game_thread_communication.insert(game_id, msg_send);
+    pub fn seat(&mut self, player_id: PlayerId, game_id: GameId, side: Side) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.seat = Some((game_id, side));
+        }
     }
 
-    pub fn send_to_game(&self, game_id: GameId, msg: Message) {
-        send_by_pipe(
-            self.game_thread_communication
-                .get(&game_id)
-                .expect("Game not found"),
-            msg,
+    pub fn watch(&mut self, player_id: PlayerId, game_id: GameId) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.watching = Some(game_id);
+        }
+    }
+
+    pub fn watching(&self, player_id: PlayerId) -> Option<GameId> {
+        self.players.get(&player_id)?.watching
+    }
+
+    // marks the player at addr as alive, returns it if it has just come back
+    pub fn seen(&mut self, addr: &SocketAddr) -> Option<PlayerId> {
+        let player_id = *self.ids_by_addr.get(addr)?;
+        let player = self.players.get_mut(&player_id)?;
+        player.last_seen = Instant::now();
+        let came_back = !player.connected;
+        player.connected = true;
+        came_back.then_some(player_id)
+    }
+
+    // player is now at addr, e.g. after it restarted or its network changed
+    pub fn rebind(&mut self, player_id: PlayerId, addr: SocketAddr) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            self.ids_by_addr.remove(&player.addr);
+            // the new address takes over the counter of the old one first
+            self.channel.authenticate(&addr, player_id, player.token);
+            // Rejoin repeated from the same address
+            if player.addr != addr {
+                self.channel.close(&player.addr);
+            }
+            player.addr = addr;
+            player.last_seen = Instant::now();
+            player.connected = true;
+            self.ids_by_addr.insert(addr, player_id);
+        }
+    }
+
+    // players in a game who went silent for `after`, they are marked as disconnected
+    pub fn connection_lost(&mut self, after: Duration) -> Vec<PlayerId> {
+        let mut lost = Vec::new();
+        for (&player_id, player) in self.players.iter_mut() {
+            if player.connected && player.seat.is_some() && player.last_seen.elapsed() > after {
+                player.connected = false;
+                lost.push(player_id);
+            }
+        }
+        lost
+    }
+
+    pub fn timed_out(&self, timeout: Duration) -> Vec<PlayerId> {
+        self.players
+            .iter()
+            .filter(|(_, p)| p.last_seen.elapsed() > timeout)
+            .map(|(&id, _)| id)
+            .collect()
+    }
+}
+
+// Player left (or stopped responding): its game is ended, the opponent and spectators
+// notified by the game thread, and all of them are forgotten.
+// Spectator leaving only stops watching.
+fn end_player(
+    players: &mut Players,
+    games: &mut GameRegistry,
+    queue: &mut MatchmakingQueue,
+    launcher: &GameLauncher,
+    player_id: PlayerId,
+) {
+    match players.game_of(player_id) {
+        Some(game_id) => {
+            games.send(game_id, GameEvent::Ended(player_id));
+            if games.finish(game_id) == Some(GameStatus::Running) {
+                launcher.stop(game_id);
+            }
+            for player_id in games
+                .players(game_id)
+                .into_iter()
+                .chain(games.spectators(game_id))
+            {
+                players.remove_player(player_id);
+            }
+        }
+        None => {
+            if let Some(game_id) = players.watching(player_id) {
+                games.send(game_id, GameEvent::Unwatch(player_id));
+                games.unwatch(game_id, player_id);
+            }
+            queue.remove(player_id);
+            players.remove_player(player_id);
+        }
+    }
+}
+
+// what starting and stopping game threads needs
+struct GameLauncher {
+    socket: UdpSocket,
+    channel: Arc<ReliableChannel>,
+    to_game_starter: mpsc::Sender<InterThreadMessage>,
+    config: Configuration,
+    // how long a game waits for a player who lost connection
+    grace_period: Duration,
+    // directory replays of all games are written to, if any
+    replays: Option<PathBuf>,
+}
+
+impl GameLauncher {
+    // replay file named by the start time and game id, as game ids start anew with the server
+    fn create_replay(
+        &self,
+        dir: &Path,
+        players: &Players,
+        game_id: GameId,
+        (left, right): (PlayerId, Opponent),
+    ) -> Option<ReplayWriter> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let header = ReplayHeader {
+            game_id,
+            started,
+            config: self.config,
+            left: ReplayPlayer {
+                player_id: left,
+                name: players.name_of(left),
+            },
+            right: ReplayPlayer {
+                player_id: match right {
+                    Opponent::Player(player_id) => player_id,
+                    Opponent::Bot(_) => BOT_PLAYER_ID,
+                },
+                name: opponent_name(players, right),
+            },
+        };
+        let path = dir.join(format!("{}-game-{}.replay", started, game_id));
+        match ReplayWriter::create(&path, &header) {
+            Ok(replay) => Some(replay),
+            Err(e) => {
+                eprintln!("Couldn't create replay {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    // both sides are taken, game thread takes over
+    fn start(&self, players: &Players, games: &mut GameRegistry, game_id: GameId) {
+        let Some((left, right)) = games.opponents(game_id) else {
+            return;
+        };
+        println!("sending to game launcher");
+        // rooms are friendly games, and there's nothing to rate guests or bots by
+        let rated = match (games.room_code(game_id), players.name_of(left), right) {
+            (None, Some(left), Opponent::Player(right)) => {
+                players.name_of(right).map(|right| (left, right))
+            }
+            _ => None,
+        };
+        let replay = self
+            .replays
+            .as_deref()
+            .and_then(|dir| self.create_replay(dir, players, game_id, (left, right)));
+        let (msg_send, msg_recv) = mpsc::channel();
+        self.to_game_starter
+            .send(InterThreadMessage::StartGame(Box::new(GameStarter {
+                game_id,
+                msg_recv,
+                left_player_addr: *players.get_player_addr(left).unwrap(),
+                left_player_id: left,
+                right: match right {
+                    Opponent::Player(right) => {
+                        Seat::Player(right, *players.get_player_addr(right).unwrap())
+                    }
+                    Opponent::Bot(difficulty) => Seat::Bot(difficulty),
+                },
+                socket: self.socket.try_clone().unwrap(),
+                channel: self.channel.clone(),
+                config: self.config,
+                grace_period: self.grace_period,
+                rated,
+                replay,
+            })))
+            .expect("Error sending message to game starter");
+        games.start(game_id, msg_send);
+    }
+
+    fn stop(&self, game_id: GameId) {
+        self.to_game_starter
+            .send(InterThreadMessage::EndGame(game_id))
+            .expect("Error sending message to game starter");
+    }
+}
+
+// tells the player it's in (and in which room), again if the answer got lost
+fn send_seat(
+    socket: &UdpSocket,
+    players: &Players,
+    games: &GameRegistry,
+    player_id: PlayerId,
+    who: &SocketAddr,
+) {
+    let token = players.token_of(player_id).unwrap();
+    send_message(socket, &Message::Ok(player_id, token), who);
+    if let Some(code) = players
+        .game_of(player_id)
+        .and_then(|game_id| games.room_code(game_id))
+    {
+        send_message(socket, &Message::InRoom(code), who);
+    }
+}
+
+// running public games with their players' names
+fn list_games(players: &Players, games: &GameRegistry) -> Vec<GameListing> {
+    games
+        .watchable()
+        .into_iter()
+        .filter_map(|game_id| {
+            let (left, right) = games.opponents(game_id)?;
+            Some(GameListing {
+                game_id,
+                left: players.name_of(left),
+                right: opponent_name(players, right),
+                spectators: games.spectators(game_id).len() as u32,
+            })
+        })
+        .collect()
+}
+
+// name shown for the right side, bots are called "bot"
+fn opponent_name(players: &Players, opponent: Opponent) -> Option<PlayerName> {
+    match opponent {
+        Opponent::Player(player_id) => players.name_of(player_id),
+        Opponent::Bot(_) => "bot".parse().ok(),
+    }
+}
+
+// starts games of every pair the queue can make now
+fn start_matches(
+    queue: &mut MatchmakingQueue,
+    players: &mut Players,
+    games: &mut GameRegistry,
+    launcher: &GameLauncher,
+) {
+    for (left, right) in queue.pair() {
+        let game_id = games.create_match(left, right);
+        println!("Matched players {} and {} in game {}", left, right, game_id);
+        players.seat(left, game_id, Side::Left);
+        players.seat(right, game_id, Side::Right);
+        launcher.start(players, games, game_id);
+    }
+}
+
+// Quick match players nobody was found for in time play against a bot.
+// Rooms are left waiting: their creator invited someone, a bot would take their seat.
+fn start_bot_matches(
+    queue: &mut MatchmakingQueue,
+    players: &mut Players,
+    games: &mut GameRegistry,
+    launcher: &GameLauncher,
+    bots: Bots,
+) {
+    for player_id in queue.take_waiting_longer_than(bots.after) {
+        let game_id = games.create_bot_match(player_id, bots.difficulty);
+        println!(
+            "Player {} plays against a {:?} bot in game {}",
+            player_id, bots.difficulty, game_id
         );
+        players.seat(player_id, game_id, Side::Left);
+        launcher.start(players, games, game_id);
     }
 }
 
-fn server(socket: &UdpSocket, to_game_starter: mpsc::Sender<InterThreadMessage>) {
+fn server(
+    socket: &UdpSocket,
+    channel: Arc<ReliableChannel>,
+    launcher: GameLauncher,
+    player_timeout: Duration,
+    rating_book: &RatingBook,
+    bots: Option<Bots>,
+) {
+    // wake up regularly even if nobody sends anything, to retransmit and check timeouts
     socket
-        .set_read_timeout(None)
+        .set_read_timeout(Some(RETRANSMIT_INTERVAL))
         .expect("set_read_timeout call failed");
-    let mut players: Players = Players::new();
+    let mut players: Players = Players::new(channel.clone());
+    let mut games = GameRegistry::new();
+    let mut queue = MatchmakingQueue::new();
+    let mut last_timeout_check = Instant::now();
+    // packets claiming to be from another player than the one at their address
+    let mut impersonations = 0;
     loop {
-        let (msg, who) = match get_message(socket) {
+        if last_timeout_check.elapsed() >= HEARTBEAT_INTERVAL {
+            for player_id in players.timed_out(player_timeout) {
+                // might be already gone with the opponent's game
+                if players.get_player_addr(player_id).is_some() {
+                    println!("Player {:?} timed out", player_id);
+                    end_player(&mut players, &mut games, &mut queue, &launcher, player_id);
+                }
+            }
+            // waiting players accept bigger rating differences by now
+            start_matches(&mut queue, &mut players, &mut games, &launcher);
+            if let Some(bots) = bots {
+                start_bot_matches(&mut queue, &mut players, &mut games, &launcher, bots);
+            }
+            for player_id in players.connection_lost(CONNECTION_LOST_AFTER) {
+                println!("Lost connection with player {:?}", player_id);
+                if let Some(game_id) = players.game_of(player_id) {
+                    games.send(game_id, GameEvent::Disconnected(player_id));
+                }
+            }
+            let rejected = channel.take_rejected() + impersonations;
+            if rejected > 0 {
+                println!("Dropped {} packets failing authentication", rejected);
+                impersonations = 0;
+            }
+            last_timeout_check = Instant::now();
+        }
+
+        channel.resend(socket);
+
+        let (msg, who) = match get_message(socket, &channel) {
             ReadType::MessageRead(msg, who) => (msg, who),
+            ReadType::VersionMismatch(version, who) => {
+                println!(
+                    "Rejected {} speaking protocol version {}, ours is {}",
+                    who, version, PROTOCOL_VERSION
+                );
+                continue;
+            }
             _ => continue,
         };
+        if let Some(player_id) = players.seen(&who) {
+            if let Some(game_id) = players.game_of(player_id) {
+                games.send(game_id, GameEvent::Reconnected(player_id, who));
+            }
+        }
+        // players may speak only for themselves
+        let claimed_id = match msg {
+            Message::EndingGame(player_id) | Message::Heartbeat(player_id) => Some(player_id),
+            Message::Move(player_move) => Some(player_move.player_id),
+            _ => None,
+        };
+        if claimed_id.is_some_and(|id| players.get_player_id(&who) != Some(id)) {
+            impersonations += 1;
+            continue;
+        }
+
         match msg {
-            Message::Join => {
-                let player_id = players.add_player(who);
-                send_message(socket, &Message::Ok(Side::Left, player_id), &who);
-
-                if let Some((left, right)) = players.is_ready() {
-                    println!("sending to game launcher");
-                    let game_id = get_game_id(left);
-                    let (msg_send, msg_recv) = mpsc::channel();
-
-                    to_game_starter
-                        .send(InterThreadMessage::StartGame(GameStarter {
-                            msg_recv,
-                            left_player_addr: *players.get_player_addr(left).unwrap(),
-                            right_player_addr: *players.get_player_addr(right).unwrap(),
-                            left_player_id: left,
-                            right_player_id: right,
-                            socket: socket.try_clone().unwrap(),
-                        }))
-                        .expect("Error sending message to game starter");
-                    players.add_game(game_id, msg_send);
+            Message::QuickMatch(named) => {
+                // Ok got lost and player asks again
+                if let Some(player_id) = players.get_player_id(&who) {
+                    send_seat(socket, &players, &games, player_id, &who);
+                    continue;
+                }
+                let refusal = named.and_then(|(name, secret)| {
+                    if players.has_name(name) {
+                        Some(Refusal::NameInUse)
+                    } else if !rating_book.claim(name, &secret) {
+                        Some(Refusal::NameClaimed)
+                    } else {
+                        None
+                    }
+                });
+                if let Some(refusal) = refusal {
+                    println!("Refused {}: {}", who, refusal);
+                    send_message(socket, &Message::Refused(refusal), &who);
+                    continue;
+                }
+                let name = named.map(|(name, _)| name);
+                let player_id = players.add_player(who, name);
+                let rating = rating_book.rating_of(name);
+                queue.push(player_id, rating);
+                println!(
+                    "Player {} waits for a match with rating {:.0}, {} in queue",
+                    player_id,
+                    rating,
+                    queue.len()
+                );
+                send_seat(socket, &players, &games, player_id, &who);
+                start_matches(&mut queue, &mut players, &mut games, &launcher);
+            }
+            Message::Spectate(game_id) => {
+                // Ok got lost and spectator asks again
+                if let Some(player_id) = players.get_player_id(&who) {
+                    send_seat(socket, &players, &games, player_id, &who);
+                    continue;
+                }
+                if !games.is_watchable(game_id) {
+                    println!("Refused {}: {}", who, Refusal::NoSuchGame);
+                    send_message(socket, &Message::Refused(Refusal::NoSuchGame), &who);
+                    continue;
+                }
+                let player_id = players.add_player(who, None);
+                players.watch(player_id, game_id);
+                games.watch(game_id, player_id);
+                println!("Player {} watches game {}", player_id, game_id);
+                send_seat(socket, &players, &games, player_id, &who);
+                games.send(game_id, GameEvent::Watch(player_id, who));
+            }
+            Message::ListGames(from) => {
+                // one datagram for one request, whoever asks, so a spoofed address can't be
+                // flooded with listings
+                let listings = list_games(&players, &games);
+                let mut page = [None; GAMES_PER_PAGE];
+                for (slot, listing) in page.iter_mut().zip(listings.iter().skip(from as usize)) {
+                    *slot = Some(*listing);
+                }
+                let page = Message::GamesPage {
+                    from,
+                    games: page,
+                    total: listings.len() as u32,
+                };
+                send_message(socket, &page, &who);
+            }
+            Message::CreateRoom(_) | Message::JoinRoom(..) => {
+                // Ok got lost and player asks again
+                if let Some(player_id) = players.get_player_id(&who) {
+                    send_seat(socket, &players, &games, player_id, &who);
+                    continue;
+                }
+                // room with a free right side, None if the player creates a new one
+                let opponent_game = match msg {
+                    Message::JoinRoom(code, password) => games.find_room(code, password).map(Some),
+                    _ => Ok(None),
+                };
+                let opponent_game = match opponent_game {
+                    Ok(game) => game,
+                    Err(refusal) => {
+                        println!("Refused {}: {}", who, refusal);
+                        send_message(socket, &Message::Refused(refusal), &who);
+                        continue;
+                    }
+                };
+                let player_id = players.add_player(who, None);
+                let (game_id, side) = match (opponent_game, msg) {
+                    (Some(game_id), _) => {
+                        games.sit_right(game_id, player_id);
+                        (game_id, Side::Right)
+                    }
+                    (None, Message::CreateRoom(password)) => {
+                        let (game_id, code) = games.create_room(player_id, password);
+                        println!("Player {} created room {}", player_id, code);
+                        (game_id, Side::Left)
+                    }
+                    (None, _) => unreachable!("JoinRoom either finds its room or is refused"),
+                };
+                players.seat(player_id, game_id, side);
+                println!("num of players{}", players.num_players());
+                send_seat(socket, &players, &games, player_id, &who);
+
+                if games.opponents(game_id).is_some() {
+                    launcher.start(&players, &mut games, game_id);
+                }
+            }
+            Message::Rejoin(token) => {
+                let Some(player_id) = players.get_player_by_token(token) else {
+                    println!("Unknown session from {}", who);
+                    send_message(socket, &Message::Refused(Refusal::UnknownSession), &who);
+                    continue;
+                };
+                players.rebind(player_id, who);
+                send_seat(socket, &players, &games, player_id, &who);
+                if let Some(game_id) = players.watching(player_id) {
+                    games.send(game_id, GameEvent::Watch(player_id, who));
+                    continue;
+                }
+                let Some(game_id) = players.game_of(player_id) else {
+                    continue;
+                };
+                if games.status(game_id) == Some(GameStatus::Running) {
+                    // player might have been restarted and waits for the game to start
+                    let side = players.side_of(player_id);
+                    channel.send(socket, &Message::Ready(side, launcher.config), &who);
+                    games.send(game_id, GameEvent::Reconnected(player_id, who));
                 }
             }
             Message::EndingGame(player_id) => {
-                let game_id = get_game_id(player_id);
-                to_game_starter
-                    .send(InterThreadMessage::EndGame(game_id))
-                    .expect("Error sending message to game starter");
-                players.send_to_game(game_id, Message::EndingGame(player_id));
-                println!("Player {:?} ended the game", player_id);
+                // the player might have timed out already
+                if players.get_player_addr(player_id).is_some() {
+                    end_player(&mut players, &mut games, &mut queue, &launcher, player_id);
+                    println!("Player {:?} ended the game", player_id);
+                }
+            }
+            Message::Heartbeat(player_id) => {
+                send_message(socket, &Message::Heartbeat(player_id), &who);
             }
             Message::Move(player_move) => {
-                players.send_to_game(
-                    get_game_id(player_move.player_id),
-                    Message::Move(player_move),
-                );
+                if let Some(game_id) = players.game_of(player_move.player_id) {
+                    games.send(game_id, GameEvent::Move(player_move));
+                }
                 // println!("Player {:?} moved: {:?}", who, player_move);
             }
             _ => {
@@ -348,3 +1363,100 @@ fn server(socket: &UdpSocket, to_game_starter: mpsc::Sender<InterThreadMessage>)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn launcher() -> (GameLauncher, mpsc::Receiver<InterThreadMessage>) {
+        let (to_game_starter, from_main) = mpsc::channel();
+        let launcher = GameLauncher {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            channel: Arc::new(ReliableChannel::new()),
+            to_game_starter,
+            config: Configuration::default(),
+            grace_period: Duration::from_secs(5),
+            replays: None,
+        };
+        (launcher, from_main)
+    }
+
+    #[test]
+    fn game_ids_stay_unique_and_increasing() {
+        let mut games = GameRegistry::new();
+        let first = games.create_match(0, 1);
+        let (room, code) = games.create_room(2, None);
+        let bot = games.create_bot_match(3, Difficulty::Easy);
+        assert!(first < room && room < bot);
+
+        games.finish(first);
+        games.finish(room);
+        assert_eq!(games.find_room(code, None), Err(Refusal::NoSuchRoom));
+        let after_finish = games.create_match(4, 5);
+        assert!(after_finish > bot);
+        // finished games are still known for a while
+        assert_eq!(games.status(first), Some(GameStatus::Finished));
+
+        // forgetting the oldest finished games doesn't give their ids away again
+        let mut last = after_finish;
+        for player_id in 0..FINISHED_GAMES_KEPT as PlayerId + 10 {
+            let game_id = games.create_match(100 + 2 * player_id, 101 + 2 * player_id);
+            assert!(game_id > last);
+            games.finish(game_id);
+            last = game_id;
+        }
+        assert_eq!(games.status(first), None);
+        assert_eq!(games.status(room), None);
+        assert_eq!(games.status(bot), Some(GameStatus::Waiting));
+        assert!(games.create_match(0, 1) > last);
+    }
+
+    #[test]
+    fn player_isnt_found_in_a_removed_game() {
+        let (launcher, from_main) = launcher();
+        let mut players = Players::new(launcher.channel.clone());
+        let mut games = GameRegistry::new();
+        let mut queue = MatchmakingQueue::new();
+        let left = players.add_player(addr(1000), None);
+        let right = players.add_player(addr(1001), None);
+        let spectator = players.add_player(addr(1002), None);
+        let game_id = games.create_match(left, right);
+        players.seat(left, game_id, Side::Left);
+        players.seat(right, game_id, Side::Right);
+        let (to_game, game_events) = mpsc::channel();
+        games.start(game_id, to_game);
+        games.watch(game_id, spectator);
+        players.watch(spectator, game_id);
+        assert_eq!(players.game_of(left), Some(game_id));
+        assert_eq!(players.game_of(right), Some(game_id));
+
+        end_player(&mut players, &mut games, &mut queue, &launcher, right);
+        assert!(matches!(
+            game_events.try_recv(),
+            Ok(GameEvent::Ended(player_id)) if player_id == right
+        ));
+        assert!(matches!(
+            from_main.try_recv(),
+            Ok(InterThreadMessage::EndGame(ended)) if ended == game_id
+        ));
+        assert_eq!(games.status(game_id), Some(GameStatus::Finished));
+        for player_id in [left, right, spectator] {
+            assert_eq!(players.game_of(player_id), None);
+            assert_eq!(players.watching(player_id), None);
+            assert_eq!(players.get_player_id(&addr(1000 + player_id as u16)), None);
+        }
+        assert_eq!(players.num_players(), 0);
+
+        // a player coming back at the same address is new, in a new game
+        let again = players.add_player(addr(1000), None);
+        assert_ne!(again, left);
+        let new_game = games.create_bot_match(again, Difficulty::Easy);
+        players.seat(again, new_game, Side::Left);
+        assert!(new_game > game_id);
+        assert_eq!(players.game_of(again), Some(new_game));
+    }
+}
@@ -0,0 +1,44 @@
+use projekt::{
+    arguments::parse_tournament,
+    external_bot::MatchSettings,
+    tournament::{Tournament, TournamentSettings},
+};
+use std::time::Instant;
+
+fn main() {
+    let (args, config) = parse_tournament();
+    let seed = args.seed.unwrap_or_else(rand::random);
+    eprintln!("Seed {}", seed);
+    let settings = TournamentSettings {
+        format: args.format,
+        rounds: args.rounds,
+        games: args.games,
+        matches: MatchSettings {
+            budget: args.budget,
+            startup_timeout: args.startup_timeout,
+            max_ticks: Some(args.max_ticks),
+        },
+        seed,
+    };
+
+    let started = Instant::now();
+    let tournament =
+        Tournament::play(&args.entrants, &config, &settings).unwrap_or_else(|(name, e)| {
+            eprintln!("{}: {}", name, e);
+            std::process::exit(1);
+        });
+    let ticks: u64 = tournament
+        .games
+        .iter()
+        .map(|game| game.report.ticks as u64)
+        .sum();
+    let seconds = started.elapsed().as_secs_f64();
+    eprintln!(
+        "{} matches, {} ticks in {:.2} s ({:.0} ticks per second)",
+        tournament.games.len(),
+        ticks,
+        seconds,
+        ticks as f64 / seconds
+    );
+    print!("{}", tournament.results().render(args.output));
+}
@@ -1,37 +1,286 @@
 use clap::Parser;
+use std::{path::PathBuf, time::Duration};
+
+use crate::codec::{set_codec, CodecKind};
+use crate::configuration::Configuration;
+use crate::controller::{ControllerKind, Difficulty};
+use crate::messages::{GameId, PlayerName, RoomCode, SessionToken, Tick};
+use crate::tournament::{Entrant, Format, OutputFormat};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct LocalArgs {
+    // configuration file (.toml or .json), defaults are used if not given
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    // who plays on the left: human (w/s) or an AI
+    #[clap(long, value_enum, default_value = "human")]
+    pub left: ControllerKind,
+
+    // who plays on the right: human (arrows) or an AI
+    #[clap(long, value_enum, default_value = "human")]
+    pub right: ControllerKind,
+
+    // how well the left AI plays
+    #[clap(long, value_enum, default_value = "normal")]
+    pub left_difficulty: Difficulty,
+
+    // how well the right AI plays
+    #[clap(long, value_enum, default_value = "normal")]
+    pub right_difficulty: Difficulty,
+}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
-struct ServerArgs {
+pub struct ReplayArgs {
+    // replay file written by the server
+    pub file: PathBuf,
+
+    // tick to start from
+    #[clap(long, default_value = "0")]
+    pub tick: Tick,
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct ServerArgs {
     #[clap(short, long, default_value = "0.0.0.0")]
-    ip: String,
+    pub ip: String,
 
     #[clap(short, long, default_value = "0")]
-    port: u16,
+    pub port: u16,
+
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    // encoding of sent messages, json is for debugging
+    #[clap(long, value_enum, default_value = "binary")]
+    pub codec: CodecKind,
+
+    // seconds without any message after which a player is considered gone
+    // and its game is ended
+    #[clap(long, default_value = "10", value_parser = parse_seconds)]
+    pub player_timeout: Duration,
+
+    // json file with ratings of named players, created if missing
+    #[clap(long, default_value = "ratings.json")]
+    pub ratings: PathBuf,
+
+    // directory every game's replay is written to (format in common/replay.rs),
+    // nothing is recorded if not given
+    #[clap(long)]
+    pub replays: Option<PathBuf>,
+
+    // seconds a quick match player waits for an opponent before it gets a bot instead,
+    // no bots if not given; a private room always waits for the one with its code
+    #[clap(long, value_parser = parse_seconds)]
+    pub bot_after: Option<Duration>,
+
+    // how well the bots play
+    #[clap(long, value_enum, default_value = "normal")]
+    pub bot_difficulty: Difficulty,
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct BotRunnerArgs {
+    // command starting the first bot, program and arguments separated by spaces,
+    // protocol described in common/external_bot.rs
+    pub first: String,
+
+    // command starting the second bot
+    pub second: String,
+
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    // matches to play, bots change sides after every one
+    #[clap(long, default_value = "1")]
+    pub matches: u32,
+
+    // milliseconds a bot has to answer every tick
+    #[clap(long, default_value = "10", value_parser = parse_millis)]
+    pub budget: Duration,
+
+    // seconds a bot has to get ready for a match
+    #[clap(long, default_value = "5", value_parser = parse_seconds)]
+    pub startup_timeout: Duration,
+
+    // a match still undecided after that many ticks ends without a winner
+    #[clap(long, default_value = "36000")]
+    pub max_ticks: Tick,
+
+    // seed of the first match's serve, the next matches get the following numbers,
+    // random if not given
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    // print reports as json lines instead of text
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct TournamentArgs {
+    // who takes part: tracking or predictive, optionally with :easy, :normal or :hard,
+    // or bot:<command> for an external bot (protocol in common/external_bot.rs)
+    #[clap(required = true, num_args = 2..)]
+    pub entrants: Vec<Entrant>,
+
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    #[clap(long, value_enum, default_value = "round-robin")]
+    pub format: Format,
+
+    // rounds of a swiss tournament, by default enough to find a single winner
+    #[clap(long)]
+    pub rounds: Option<u32>,
+
+    // matches of every pairing, entrants change sides after each
+    #[clap(long, default_value = "2")]
+    pub games: u32,
+
+    // milliseconds an external bot has to answer every tick
+    #[clap(long, default_value = "10", value_parser = parse_millis)]
+    pub budget: Duration,
+
+    // seconds an external bot has to get ready for a match
+    #[clap(long, default_value = "5", value_parser = parse_seconds)]
+    pub startup_timeout: Duration,
+
+    // a match still undecided after that many ticks is a draw
+    #[clap(long, default_value = "36000")]
+    pub max_ticks: Tick,
+
+    // seed of serves and AIs, random if not given
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    #[clap(long, value_enum, default_value = "text")]
+    pub output: OutputFormat,
 }
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
-struct PlayerArgs {
+pub struct PlayerArgs {
     #[clap(long, default_value = "127.0.0.1")]
-    server_ip: String,
+    pub server_ip: String,
 
     #[clap(long, required = true)]
-    server_port: u16,
+    pub server_port: u16,
 
     #[clap(short, long, default_value = "0.0.0.0")]
-    ip: String,
+    pub ip: String,
 
     #[clap(short, long, default_value = "0")]
-    port: u16,
+    pub port: u16,
+
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    // encoding of sent messages, json is for debugging
+    #[clap(long, value_enum, default_value = "binary")]
+    pub codec: CodecKind,
+
+    // how far in the past (in milliseconds) the game is rendered,
+    // more is smoother on a bad connection but lags behind the server
+    #[clap(long, default_value = "100", value_parser = parse_millis)]
+    pub interpolation_delay: Duration,
+
+    // when playing on the right, show the game flipped so that own paddle is on the left
+    #[clap(long)]
+    pub mirror: bool,
+
+    // session token printed by the player when it joined, to come back to that game
+    #[clap(long, conflicts_with_all = ["room", "create_room"])]
+    pub rejoin: Option<SessionToken>,
+
+    // start a private game, its code gets printed for the opponent
+    #[clap(long, conflicts_with = "room")]
+    pub create_room: bool,
+
+    // join the private game with this code
+    #[clap(long)]
+    pub room: Option<RoomCode>,
+
+    // password of the room being created or joined
+    #[clap(long)]
+    pub password: Option<String>,
+
+    // name the server keeps the rating under, quick match games are rated only when named
+    #[clap(long, requires = "secret", conflicts_with_all = ["rejoin", "room", "create_room"])]
+    pub name: Option<PlayerName>,
+
+    // the name belongs to whoever first played under it with this secret,
+    // it has to be given every time
+    #[clap(long, requires = "name")]
+    pub secret: Option<String>,
+
+    // watch the game with this id instead of playing, see --list-games
+    #[clap(long, conflicts_with_all = ["rejoin", "room", "create_room", "name"])]
+    pub spectate: Option<GameId>,
+
+    // print the games which can be watched and exit
+    #[clap(long)]
+    pub list_games: bool,
+}
+
+fn parse_millis(arg: &str) -> Result<Duration, std::num::ParseIntError> {
+    arg.parse().map(Duration::from_millis)
+}
+
+fn parse_seconds(arg: &str) -> Result<Duration, std::num::ParseIntError> {
+    arg.parse().map(Duration::from_secs)
+}
+
+// exits with a readable message instead of panicking on a bad file
+fn load_configuration(path: Option<PathBuf>) -> Configuration {
+    match path {
+        Some(path) => Configuration::from_file(&path).unwrap_or_else(|e| {
+            eprintln!("Error in {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => Configuration::default(),
+    }
+}
+
+pub fn parse_local() -> (LocalArgs, Configuration) {
+    let args = LocalArgs::parse();
+    let config = load_configuration(args.config.clone());
+    (args, config)
 }
 
-pub fn parse_server() -> (String, u16) {
+// also selects the codec for the whole process
+pub fn parse_server() -> (ServerArgs, Configuration) {
     let args = ServerArgs::parse();
-    (args.ip, args.port)
+    set_codec(args.codec);
+    let config = load_configuration(args.config.clone());
+    (args, config)
 }
 
-pub fn parse_player() -> (String, u16, String, u16) {
+// also selects the codec for the whole process
+pub fn parse_player() -> (PlayerArgs, Configuration) {
     let args = PlayerArgs::parse();
-    (args.ip, args.port, args.server_ip, args.server_port)
+    set_codec(args.codec);
+    let config = load_configuration(args.config.clone());
+    (args, config)
+}
+
+pub fn parse_bot_runner() -> (BotRunnerArgs, Configuration) {
+    let args = BotRunnerArgs::parse();
+    let config = load_configuration(args.config.clone());
+    (args, config)
+}
+
+pub fn parse_tournament() -> (TournamentArgs, Configuration) {
+    let args = TournamentArgs::parse();
+    let config = load_configuration(args.config.clone());
+    (args, config)
+}
+
+pub fn parse_replay() -> ReplayArgs {
+    ReplayArgs::parse()
 }
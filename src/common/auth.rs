@@ -0,0 +1,83 @@
+use bincode::Options;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::messages::{Packet, PlayerId, SessionToken};
+
+// Players sign everything they send with the session token they got in Ok,
+// so that the server knows a packet comes from the player it claims to be from.
+// The token itself only travels in Ok and Rejoin.
+// Every signature has a counter, which goes up with every packet sent, and the server drops
+// packets not newer than the newest one it accepted, so a recorded packet can't be sent again.
+// It starts at the time in microseconds, so a player run again with --rejoin is ahead too.
+//
+// tag = first TAG_LEN bytes of HMAC-SHA256(key: token, data: player id ++ counter ++ packet),
+// integers little endian, packet in the binary codec, whatever codec the datagram uses
+
+const TAG_LEN: usize = 16;
+pub type Tag = [u8; TAG_LEN];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Signature {
+    pub player_id: PlayerId,
+    pub counter: u64,
+    pub tag: Tag,
+}
+
+fn mac(player_id: PlayerId, counter: u64, token: SessionToken, packet: &Packet) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&token.to_le_bytes()).unwrap();
+    mac.update(&player_id.to_le_bytes());
+    mac.update(&counter.to_le_bytes());
+    mac.update(&bincode::DefaultOptions::new().serialize(packet).unwrap());
+    mac
+}
+
+pub fn sign(packet: &Packet, player_id: PlayerId, counter: u64, token: SessionToken) -> Signature {
+    let mut tag = [0; TAG_LEN];
+    tag.copy_from_slice(
+        &mac(player_id, counter, token, packet)
+            .finalize()
+            .into_bytes()[..TAG_LEN],
+    );
+    Signature {
+        player_id,
+        counter,
+        tag,
+    }
+}
+
+// only whether the tag is right, the counter is checked by the receiver
+pub fn verify(packet: &Packet, signature: &Signature, token: SessionToken) -> bool {
+    mac(signature.player_id, signature.counter, token, packet)
+        .verify_truncated_left(&signature.tag)
+        .is_ok()
+}
+
+// session everything sent by this process is signed for, set by the player once it joined,
+// with the counter of the last packet signed
+static SIGNING_KEY: Mutex<Option<(PlayerId, SessionToken, u64)>> = Mutex::new(None);
+
+pub fn set_signing_key(player_id: PlayerId, token: SessionToken) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64);
+    *SIGNING_KEY.lock().unwrap() = Some((player_id, token, now));
+}
+
+// signature of the next packet sent, None until the signing key is set
+pub fn sign_next(packet: &Packet) -> Option<Signature> {
+    let mut key = SIGNING_KEY.lock().unwrap();
+    let (player_id, token, counter) = key.as_mut()?;
+    *counter += 1;
+    Some(sign(packet, *player_id, *counter, *token))
+}
+
+// room passwords travel (and are compared) only hashed
+pub type PasswordHash = [u8; 32];
+
+pub fn hash_password(password: &str) -> PasswordHash {
+    Sha256::digest(password.as_bytes()).into()
+}
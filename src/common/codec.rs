@@ -0,0 +1,249 @@
+use bincode::Options;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use super::messages::Datagram;
+
+// Wire format
+//
+// Every datagram starts with a 4 byte header, which stays the same in all protocol versions,
+// so that peers speaking different versions can still tell each other about it:
+//   bytes 0..2  PROTOCOL_MAGIC
+//   byte  2     PROTOCOL_VERSION of the sender
+//   byte  3     tag of the codec used for the rest of the datagram (CodecKind),
+//               or VERSION_MISMATCH_TAG, then there is nothing more
+// After the header comes the Datagram (a Message, possibly with reliability data and
+// player's signature) encoded by the codec.
+// Receivers decode by the tag, so the codec is only a choice of the sender.
+
+pub const PROTOCOL_MAGIC: [u8; 2] = *b"PG";
+// bump whenever Datagram or anything inside it changes
+pub const PROTOCOL_VERSION: u8 = 15;
+const VERSION_MISMATCH_TAG: u8 = 0xFF;
+const HEADER_LEN: usize = 4;
+
+pub trait Codec {
+    fn encode(&self, datagram: &Datagram) -> Vec<u8>;
+    fn decode(&self, bytes: &[u8]) -> Result<Datagram, String>;
+}
+
+// compact, used by default
+pub struct BinaryCodec;
+
+// human readable, for debugging
+pub struct JsonCodec;
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+impl Codec for BinaryCodec {
+    fn encode(&self, datagram: &Datagram) -> Vec<u8> {
+        bincode_options().serialize(datagram).unwrap()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Datagram, String> {
+        bincode_options()
+            .deserialize(bytes)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Codec for JsonCodec {
+    fn encode(&self, datagram: &Datagram) -> Vec<u8> {
+        serde_json::to_vec(datagram).unwrap()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Datagram, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[repr(u8)]
+pub enum CodecKind {
+    Binary = 0,
+    Json = 1,
+}
+
+impl CodecKind {
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(CodecKind::Binary),
+            1 => Some(CodecKind::Json),
+            _ => None,
+        }
+    }
+
+    pub fn codec(self) -> &'static dyn Codec {
+        match self {
+            CodecKind::Binary => &BinaryCodec,
+            CodecKind::Json => &JsonCodec,
+        }
+    }
+}
+
+// codec used for sending by this process
+static SEND_CODEC: AtomicU8 = AtomicU8::new(CodecKind::Binary as u8);
+
+pub fn set_codec(kind: CodecKind) {
+    SEND_CODEC.store(kind as u8, Ordering::Relaxed);
+}
+
+pub fn get_codec() -> CodecKind {
+    CodecKind::from_tag(SEND_CODEC.load(Ordering::Relaxed)).unwrap()
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    // not our protocol at all
+    NoMagic,
+    // peer speaks another version and wants to be told ours
+    OtherVersion(u8),
+    // peer tells us it speaks another version
+    VersionMismatch(u8),
+    Malformed(String),
+}
+
+fn header(tag: u8) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(64);
+    bytes.extend_from_slice(&PROTOCOL_MAGIC);
+    bytes.push(PROTOCOL_VERSION);
+    bytes.push(tag);
+    bytes
+}
+
+pub fn encode(datagram: &Datagram) -> Vec<u8> {
+    let kind = get_codec();
+    let mut bytes = header(kind as u8);
+    bytes.extend(kind.codec().encode(datagram));
+    bytes
+}
+
+// reply for peers speaking another protocol version
+pub fn encode_version_mismatch() -> Vec<u8> {
+    header(VERSION_MISMATCH_TAG)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Datagram, DecodeError> {
+    if bytes.len() < HEADER_LEN || bytes[..2] != PROTOCOL_MAGIC {
+        return Err(DecodeError::NoMagic);
+    }
+    let (version, tag) = (bytes[2], bytes[3]);
+    if tag == VERSION_MISMATCH_TAG {
+        return Err(DecodeError::VersionMismatch(version));
+    }
+    if version != PROTOCOL_VERSION {
+        return Err(DecodeError::OtherVersion(version));
+    }
+    match CodecKind::from_tag(tag) {
+        Some(kind) => kind
+            .codec()
+            .decode(&bytes[HEADER_LEN..])
+            .map_err(DecodeError::Malformed),
+        None => Err(DecodeError::Malformed(format!("unknown codec {}", tag))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{hash_password, sign};
+    use crate::configuration::Configuration;
+    use crate::messages::{
+        GameListing, GameState, Message, Packet, Pause, PlayerMove, Refusal, GAMES_PER_PAGE,
+    };
+    use crate::rules::Side;
+
+    fn datagrams() -> Vec<Datagram> {
+        let name = "ann".parse().unwrap();
+        let mut games = [None; GAMES_PER_PAGE];
+        games[0] = Some(GameListing {
+            game_id: 4,
+            left: Some(name),
+            right: None,
+            spectators: 2,
+        });
+        let mut state = GameState::new(7, (1.5, -2.25), (5.0, 300.0), (795.0, 0.1));
+        state.right_input_tick = 6;
+        let messages = [
+            Message::Ok(3, u64::MAX),
+            Message::InRoom("K7QXM".parse().unwrap()),
+            Message::Refused(Refusal::NameClaimed),
+            Message::State(state),
+            Message::Ready(Some(Side::Right), Configuration::default()),
+            Message::MatchOver {
+                winner: Side::Left,
+                score: (11, 9),
+            },
+            Message::Paused(Pause::WaitingFor(3, 10)),
+            Message::GamesPage {
+                from: 8,
+                games,
+                total: 9,
+            },
+            Message::QuickMatch(Some((name, hash_password("secret")))),
+            Message::CreateRoom(None),
+            Message::ListGames(4),
+            Message::Move(PlayerMove {
+                player_id: 3,
+                tick: 100,
+                up: true,
+                down: false,
+            }),
+        ];
+        let mut datagrams = Vec::new();
+        for (i, msg) in messages.into_iter().enumerate() {
+            for packet in [Packet::Unreliable(msg), Packet::Reliable(9, i as u32, msg)] {
+                datagrams.push(Datagram {
+                    packet,
+                    signature: None,
+                });
+                datagrams.push(Datagram {
+                    packet,
+                    signature: Some(sign(&packet, 3, i as u64, 42)),
+                });
+            }
+        }
+        datagrams.push(Datagram {
+            packet: Packet::Ack(u32::MAX, u32::MAX),
+            signature: None,
+        });
+        datagrams
+    }
+
+    #[test]
+    fn codecs_give_back_what_they_got() {
+        for kind in [CodecKind::Binary, CodecKind::Json] {
+            for datagram in datagrams() {
+                let bytes = kind.codec().encode(&datagram);
+                assert_eq!(kind.codec().decode(&bytes), Ok(datagram), "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn header_tells_what_can_be_read() {
+        let datagram = datagrams()[0];
+        let mut bytes = header(CodecKind::Json as u8);
+        bytes.extend(JsonCodec.encode(&datagram));
+        assert_eq!(decode(&bytes).ok(), Some(datagram));
+
+        bytes[2] = PROTOCOL_VERSION - 1;
+        assert!(
+            matches!(decode(&bytes), Err(DecodeError::OtherVersion(v)) if v == PROTOCOL_VERSION - 1)
+        );
+        assert!(matches!(
+            decode(&encode_version_mismatch()),
+            Err(DecodeError::VersionMismatch(PROTOCOL_VERSION))
+        ));
+        assert!(matches!(decode(b"GET /"), Err(DecodeError::NoMagic)));
+        assert!(matches!(decode(b"PG"), Err(DecodeError::NoMagic)));
+
+        let mut unknown = header(7);
+        unknown.extend(JsonCodec.encode(&datagram));
+        assert!(matches!(decode(&unknown), Err(DecodeError::Malformed(_))));
+        let mut cut = header(CodecKind::Binary as u8);
+        cut.extend(&BinaryCodec.encode(&datagram)[..3]);
+        assert!(matches!(decode(&cut), Err(DecodeError::Malformed(_))));
+    }
+}
@@ -0,0 +1,535 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use super::gym::{Action, EnvOptions, PongEnv};
+use super::messages::{GameState, Tick};
+use crate::configuration::Configuration;
+use crate::rules::Side;
+
+// Bots written in any language, run as separate processes which talk over stdin and stdout,
+// one json object per line each way. The bot's stderr is left to the terminal, for debugging.
+//
+// Runner to bot:
+//   {"type":"start","match":1,"side":"Left","configuration":{...}}
+//       a match begins, the bot plays on `side`; it answers {"ready":1}, with the number
+//       of the match, within the startup timeout
+//   {"type":"state","tick":0,"state":{GameState},"ball_velocity":[vx,vy],"paddle_velocities":[left,right],"score":[left,right]}
+//       every tick, sent to both bots at once, the bot answers {"tick":0,"action":"up"},
+//       "down" or "stay", with the tick of the state, within the tick's budget,
+//       no valid answer in time and the paddle stays for that tick
+//   {"type":"end","winner":"Left" or null,"score":[left,right]}
+//       the match is over, no answer, a start of the next match may follow
+//
+// Positions are in pixels with y growing downwards, velocities in pixels per second.
+// Answers for earlier ticks or matches, late or repeated, are thrown away when they arrive.
+// A bot which exits or doesn't get ready in time forfeits the match.
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BotState {
+    pub state: GameState,
+    pub ball_velocity: (f32, f32),
+    // paddles only move vertically
+    pub paddle_velocities: (f32, f32),
+    pub score: (u32, u32),
+}
+
+impl BotState {
+    pub fn of(env: &PongEnv) -> Self {
+        let pong = env.pong();
+        Self {
+            state: GameState::new(
+                env.tick(),
+                pong.ball.get_position().into(),
+                pong.left_paddle.get_position().into(),
+                pong.right_paddle.get_position().into(),
+            ),
+            ball_velocity: pong.ball.get_velocity().into(),
+            paddle_velocities: (
+                pong.left_paddle.get_velocity(),
+                pong.right_paddle.get_velocity(),
+            ),
+            score: pong.game.score,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ToBot<'a> {
+    Start {
+        #[serde(rename = "match")]
+        number: u32,
+        side: Side,
+        configuration: &'a Configuration,
+    },
+    State {
+        tick: Tick,
+        #[serde(flatten)]
+        state: &'a BotState,
+    },
+    End {
+        winner: Option<Side>,
+        score: (u32, u32),
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FromBot {
+    // number of the match
+    Ready { ready: u32 },
+    Action { tick: Tick, action: Action },
+}
+
+#[derive(Debug)]
+pub enum BotError {
+    Spawn(io::Error),
+    // stdout closed, the process most likely ended
+    Exited,
+    Io(io::Error),
+    NotReady,
+    Protocol(String),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BotError::Spawn(e) => write!(f, "couldn't start bot: {}", e),
+            BotError::Exited => write!(f, "bot exited"),
+            BotError::Io(e) => write!(f, "couldn't write to bot: {}", e),
+            BotError::NotReady => write!(f, "bot didn't get ready in time"),
+            BotError::Protocol(e) => write!(f, "bot broke the protocol: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BotError {}
+
+// Anything play_match can seat on a side: a bot process, or an AI of this crate.
+pub trait Bot {
+    // a match begins, the bot has `timeout` to get ready
+    fn start(
+        &mut self,
+        side: Side,
+        configuration: &Configuration,
+        timeout: Duration,
+    ) -> Result<(), BotError>;
+
+    // the game before a tick, the bot has `budget` from now to decide what to do
+    fn request(&mut self, env: &PongEnv, budget: Duration) -> Result<(), BotError>;
+
+    // the decision for the last request
+    fn action(&mut self) -> Result<Action, BotError>;
+
+    fn end(&mut self, _winner: Option<Side>, _score: (u32, u32)) -> Result<(), BotError> {
+        Ok(())
+    }
+
+    // (ticks without a decision in time, invalid decisions) since start
+    fn faults(&self) -> (u32, u32) {
+        (0, 0)
+    }
+}
+
+// A running bot process.
+pub struct ExternalBot {
+    // program and its arguments, separated by whitespace
+    pub command: String,
+    program: String,
+    args: Vec<String>,
+    child: Child,
+    stdin: ChildStdin,
+    // lines of the bot's stdout, read by a thread of their own so that waiting can time out
+    replies: Receiver<String>,
+    // matches started, the current one's number
+    matches: u32,
+    // tick of the last state sent, only the answer to it counts
+    requested: Tick,
+    // when the answer to the last request is due
+    deadline: Instant,
+    // ticks without a valid answer in time, and answers which weren't valid, since start
+    pub timeouts: u32,
+    pub invalid: u32,
+}
+
+impl ExternalBot {
+    pub fn spawn(command: &str) -> Result<Self, BotError> {
+        let mut words = command.split_whitespace().map(String::from);
+        let program = words
+            .next()
+            .ok_or_else(|| BotError::Spawn(io::Error::other("empty command")))?;
+        Self::spawn_program(program, words.collect())
+    }
+
+    // for arguments with whitespace in them
+    pub fn spawn_program(program: String, args: Vec<String>) -> Result<Self, BotError> {
+        let mut child = Command::new(&program)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(BotError::Spawn)?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, replies) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            command: std::iter::once(&program)
+                .chain(&args)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" "),
+            program,
+            args,
+            child,
+            stdin,
+            replies,
+            matches: 0,
+            requested: 0,
+            deadline: Instant::now(),
+            timeouts: 0,
+            invalid: 0,
+        })
+    }
+
+    fn send(&mut self, message: &ToBot) -> Result<(), BotError> {
+        let mut line = serde_json::to_string(message).expect("messages to bots serialize");
+        line.push('\n');
+        self.stdin
+            .write_all(line.as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .map_err(BotError::Io)
+    }
+
+    // Next line within `timeout`, Ok(None) if none came.
+    fn receive(&mut self, timeout: Duration) -> Result<Option<String>, BotError> {
+        match self.replies.recv_timeout(timeout) {
+            Ok(line) => Ok(Some(line)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(BotError::Exited),
+        }
+    }
+}
+
+impl Bot for ExternalBot {
+    // Tells the bot a match begins and waits until it's ready.
+    // A bot which exited during an earlier match is started again first.
+    fn start(
+        &mut self,
+        side: Side,
+        configuration: &Configuration,
+        timeout: Duration,
+    ) -> Result<(), BotError> {
+        if !matches!(self.child.try_wait(), Ok(None)) {
+            let matches = self.matches;
+            *self = Self::spawn_program(self.program.clone(), self.args.clone())?;
+            self.matches = matches;
+        }
+        self.timeouts = 0;
+        self.invalid = 0;
+        self.matches += 1;
+        self.send(&ToBot::Start {
+            number: self.matches,
+            side,
+            configuration,
+        })?;
+        let deadline = Instant::now() + timeout;
+        // answers to the previous match's last states, or its late "ready", may still be on the way
+        loop {
+            let line = self
+                .receive(deadline.saturating_duration_since(Instant::now()))?
+                .ok_or(BotError::NotReady)?;
+            match serde_json::from_str(&line) {
+                Ok(FromBot::Ready { ready }) if ready == self.matches => return Ok(()),
+                Ok(_) => {}
+                Err(_) => {
+                    return Err(BotError::Protocol(format!(
+                        "expected {{\"ready\":{}}}, got {}",
+                        self.matches, line
+                    )))
+                }
+            }
+        }
+    }
+
+    fn request(&mut self, env: &PongEnv, budget: Duration) -> Result<(), BotError> {
+        self.requested = env.tick();
+        self.send(&ToBot::State {
+            tick: self.requested,
+            state: &BotState::of(env),
+        })?;
+        self.deadline = Instant::now() + budget;
+        Ok(())
+    }
+
+    // waits for the answer, Stay if it doesn't come in time
+    fn action(&mut self) -> Result<Action, BotError> {
+        loop {
+            let Some(line) =
+                self.receive(self.deadline.saturating_duration_since(Instant::now()))?
+            else {
+                self.timeouts += 1;
+                return Ok(Action::Stay);
+            };
+            match serde_json::from_str(&line) {
+                Ok(FromBot::Action { tick, action }) if tick == self.requested => {
+                    return Ok(action)
+                }
+                // late answers to earlier ticks
+                Ok(FromBot::Action { tick, .. }) if tick < self.requested => {}
+                Ok(FromBot::Ready { .. }) => {}
+                _ => {
+                    self.invalid += 1;
+                    return Ok(Action::Stay);
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, winner: Option<Side>, score: (u32, u32)) -> Result<(), BotError> {
+        self.send(&ToBot::End { winner, score })
+    }
+
+    fn faults(&self) -> (u32, u32) {
+        (self.timeouts, self.invalid)
+    }
+}
+
+impl Drop for ExternalBot {
+    fn drop(&mut self) {
+        // nothing to do if it has already ended
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MatchSettings {
+    // time a bot has to answer every tick
+    pub budget: Duration,
+    // time a bot has to get ready for a match
+    pub startup_timeout: Duration,
+    // the match ends undecided after that many ticks, None for no limit
+    pub max_ticks: Option<Tick>,
+}
+
+// side which lost the match by failing, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct Forfeit {
+    pub side: Side,
+    pub reason: String,
+}
+
+// (left, right) in every pair
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchReport {
+    pub seed: u64,
+    pub score: (u32, u32),
+    // None if the tick limit came first
+    pub winner: Option<Side>,
+    pub ticks: Tick,
+    pub forfeit: Option<Forfeit>,
+    pub timeouts: (u32, u32),
+    pub invalid: (u32, u32),
+    // times the ball was hit by a paddle, over the points played it's the average rally
+    pub hits: u32,
+}
+
+// Plays a whole match between two bots, `seed` picks the first serve (see PongEnv::reset).
+// The simulation waits for the bots, so it goes as fast as they answer.
+pub fn play_match(
+    configuration: &Configuration,
+    left: &mut dyn Bot,
+    right: &mut dyn Bot,
+    seed: u64,
+    settings: &MatchSettings,
+) -> MatchReport {
+    let options = EnvOptions {
+        max_steps: settings.max_ticks,
+        ..EnvOptions::default()
+    };
+    let mut env = PongEnv::new(*configuration, options);
+    env.reset(seed);
+
+    let forfeit = |side: Side, error: BotError| Forfeit {
+        side,
+        reason: error.to_string(),
+    };
+    let mut failed = left
+        .start(Side::Left, configuration, settings.startup_timeout)
+        .err()
+        .map(|e| forfeit(Side::Left, e));
+    if failed.is_none() {
+        failed = right
+            .start(Side::Right, configuration, settings.startup_timeout)
+            .err()
+            .map(|e| forfeit(Side::Right, e));
+    }
+    let mut hits = 0;
+    while failed.is_none() && !env.is_done() {
+        // both think at the same time
+        let actions = left
+            .request(&env, settings.budget)
+            .map_err(|e| forfeit(Side::Left, e))
+            .and_then(|_| {
+                right
+                    .request(&env, settings.budget)
+                    .map_err(|e| forfeit(Side::Right, e))
+            })
+            .and_then(|_| left.action().map_err(|e| forfeit(Side::Left, e)))
+            .and_then(|l| {
+                right
+                    .action()
+                    .map(|r| (l, r))
+                    .map_err(|e| forfeit(Side::Right, e))
+            });
+        match actions {
+            Ok((l, r)) => {
+                let direction = env.pong().ball.get_velocity().x.signum();
+                let (_, reward, _) = env.step(l, r);
+                // only paddles turn the ball back, a point serves it anew
+                if reward == 0.0 && env.pong().ball.get_velocity().x.signum() != direction {
+                    hits += 1;
+                }
+            }
+            Err(e) => failed = Some(e),
+        }
+    }
+
+    let score = env.pong().game.score;
+    let winner = match &failed {
+        Some(forfeit) => Some(forfeit.side.opposite()),
+        None => env.pong().game.winner(),
+    };
+    // a bot that failed may be gone, it gets started again for the next match anyway
+    let _ = left.end(winner, score);
+    let _ = right.end(winner, score);
+    MatchReport {
+        seed,
+        score,
+        winner,
+        ticks: env.tick(),
+        forfeit: failed,
+        timeouts: (left.faults().0, right.faults().0),
+        invalid: (left.faults().1, right.faults().1),
+        hits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // bot following a script, which reads a line for every message it's waiting for
+    fn scripted(script: &str) -> ExternalBot {
+        ExternalBot::spawn_program("sh".to_string(), vec!["-c".to_string(), script.to_string()])
+            .unwrap()
+    }
+
+    fn env() -> PongEnv {
+        PongEnv::new(Configuration::default(), EnvOptions::default())
+    }
+
+    fn start(bot: &mut ExternalBot) -> Result<(), BotError> {
+        bot.start(
+            Side::Left,
+            &Configuration::default(),
+            Duration::from_secs(5),
+        )
+    }
+
+    // answer to the state of the env's tick
+    fn decide(bot: &mut ExternalBot, env: &mut PongEnv, budget: Duration) -> Action {
+        bot.request(env, budget).unwrap();
+        let action = bot.action().unwrap();
+        env.step(Action::Stay, Action::Stay);
+        action
+    }
+
+    #[test]
+    fn late_answer_is_a_timeout_and_is_thrown_away() {
+        let mut bot = scripted(
+            r#"read m; echo '{"ready":1}'
+            read m; sleep 1; echo '{"tick":0,"action":"up"}'
+            read m; echo '{"tick":1,"action":"down"}'"#,
+        );
+        let mut env = env();
+        start(&mut bot).unwrap();
+        assert_eq!(
+            decide(&mut bot, &mut env, Duration::from_millis(100)),
+            Action::Stay
+        );
+        assert_eq!(
+            decide(&mut bot, &mut env, Duration::from_secs(5)),
+            Action::Down
+        );
+        assert_eq!(bot.faults(), (1, 0));
+    }
+
+    #[test]
+    fn invalid_answers_leave_the_paddle_still() {
+        let mut bot = scripted(
+            r#"read m; echo '{"ready":1}'
+            read m; echo '{"tick":0,"action":"jump"}'
+            read m; echo 'up'
+            read m; echo '{"tick":7,"action":"up"}'
+            read m; echo '{"tick":3,"action":"up"}'"#,
+        );
+        let mut env = env();
+        start(&mut bot).unwrap();
+        let budget = Duration::from_secs(5);
+        for _ in 0..3 {
+            assert_eq!(decide(&mut bot, &mut env, budget), Action::Stay);
+        }
+        assert_eq!(decide(&mut bot, &mut env, budget), Action::Up);
+        assert_eq!(bot.faults(), (0, 3));
+    }
+
+    #[test]
+    fn extra_lines_are_thrown_away() {
+        let mut bot = scripted(
+            r#"read m; echo '{"ready":1}'
+            read m; echo '{"tick":0,"action":"up"}'; echo '{"tick":0,"action":"down"}'
+            echo '{"ready":1}'
+            read m; echo '{"tick":1,"action":"stay"}'"#,
+        );
+        let mut env = env();
+        start(&mut bot).unwrap();
+        let budget = Duration::from_secs(5);
+        assert_eq!(decide(&mut bot, &mut env, budget), Action::Up);
+        assert_eq!(decide(&mut bot, &mut env, budget), Action::Stay);
+        assert_eq!(bot.faults(), (0, 0));
+    }
+
+    #[test]
+    fn late_ready_doesnt_start_the_next_match() {
+        let mut bot = scripted(
+            r#"read m; sleep 1; echo '{"ready":1}'
+            read m; echo '{"tick":0,"action":"up"}'; echo '{"ready":2}'
+            read m; echo '{"tick":0,"action":"down"}'"#,
+        );
+        let configuration = Configuration::default();
+        assert!(matches!(
+            bot.start(Side::Left, &configuration, Duration::from_millis(100)),
+            Err(BotError::NotReady)
+        ));
+        // neither the late "ready" nor an answer before the right one start the second match
+        start(&mut bot).unwrap();
+        let mut env = env();
+        assert_eq!(
+            decide(&mut bot, &mut env, Duration::from_secs(5)),
+            Action::Down
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::messages::Tick;
+use super::multiplayer_pong::MultiplayerPong;
+use crate::configuration::{Configuration, FromConfiguration};
+use crate::rules::Match;
+use crate::state::RoundResult;
+
+// Headless environment for training agents, in the style of OpenAI Gym: both paddles
+// are moved by agents, a step is frame_skip ticks of MultiplayerPong::multi_game_round.
+//
+// Reward is from the left player's point of view: +1 for its point, -1 for the right
+// player's one, so the right agent's reward is its negation.
+// Episode is done when the match is decided (configuration.rules) or after max_steps.
+// Everything but reset's seed is deterministic, so the same seed and actions give
+// the same episode.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Stay,
+    Up,
+    Down,
+}
+
+impl Action {
+    // all of them, by index, for agents choosing a number
+    pub const ALL: [Action; 3] = [Action::Stay, Action::Up, Action::Down];
+
+    // as keys held, (up, down)
+    pub fn keys(self) -> (bool, bool) {
+        match self {
+            Action::Stay => (false, false),
+            Action::Up => (true, false),
+            Action::Down => (false, true),
+        }
+    }
+
+    // both keys held cancel out
+    pub fn from_keys((up, down): (bool, bool)) -> Self {
+        match (up, down) {
+            (true, false) => Action::Up,
+            (false, true) => Action::Down,
+            _ => Action::Stay,
+        }
+    }
+}
+
+// What the environment does and what agents see.
+// Observation is made of the enabled parts, in this order, in pixels and pixels per second:
+//   ball_position     ball x, ball y
+//   ball_velocity     ball vx, ball vy
+//   paddle_positions  left paddle y, right paddle y (paddles don't move sideways)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvOptions {
+    // ticks every step lasts, the action is held through all of them
+    pub frame_skip: u32,
+    pub ball_position: bool,
+    pub ball_velocity: bool,
+    pub paddle_positions: bool,
+    // episode ends after that many steps even if the match isn't decided, None for no limit
+    pub max_steps: Option<u32>,
+}
+
+impl Default for EnvOptions {
+    fn default() -> Self {
+        Self {
+            frame_skip: 1,
+            ball_position: true,
+            ball_velocity: true,
+            paddle_positions: true,
+            max_steps: None,
+        }
+    }
+}
+
+impl EnvOptions {
+    pub fn observation_size(&self) -> usize {
+        2 * (self.ball_position as usize
+            + self.ball_velocity as usize
+            + self.paddle_positions as usize)
+    }
+}
+
+pub struct PongEnv {
+    config: Configuration,
+    options: EnvOptions,
+    pong: MultiplayerPong,
+    tick: Tick,
+    steps: u32,
+    done: bool,
+}
+
+impl PongEnv {
+    pub fn new(config: Configuration, options: EnvOptions) -> Self {
+        assert!(
+            options.observation_size() > 0,
+            "observation needs at least one part"
+        );
+        let mut env = Self {
+            config,
+            options,
+            pong: MultiplayerPong::from_configuration(&config),
+            tick: 0,
+            steps: 0,
+            done: false,
+        };
+        env.reset(0);
+        env
+    }
+
+    pub fn options(&self) -> &EnvOptions {
+        &self.options
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // ticks played since reset
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    pub fn pong(&self) -> &MultiplayerPong {
+        &self.pong
+    }
+
+    // New episode, the seed picks the serve: side the ball goes to first and its vertical
+    // speed, up to the configured one either way. The horizontal speed stays as configured.
+    pub fn reset(&mut self, seed: u64) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let configured = self.config.ball_initial_velocity;
+        let velocity = glam::vec2(
+            if rng.gen() {
+                configured.x
+            } else {
+                -configured.x
+            },
+            configured.y.abs() * rng.gen_range(-1.0..=1.0),
+        );
+        self.pong = MultiplayerPong::from_configuration(&self.config);
+        self.pong.ball.initial_velocity = velocity;
+        self.pong.ball.set_velocity(velocity);
+        self.pong.game = Match::new(self.config.rules, self.pong.ball.first_serve());
+        self.tick = 0;
+        self.steps = 0;
+        self.done = false;
+        self.observation()
+    }
+
+    // Plays frame_skip ticks with both actions held, returns (observation, reward, done).
+    // Once done, steps change nothing until reset.
+    pub fn step(&mut self, left: Action, right: Action) -> (Vec<f32>, f32, bool) {
+        let reward = self.advance(left, right);
+        (self.observation(), reward, self.done)
+    }
+
+    // step without allocating, the observation is written to `observation`
+    pub fn step_into(
+        &mut self,
+        left: Action,
+        right: Action,
+        observation: &mut [f32],
+    ) -> (f32, bool) {
+        let reward = self.advance(left, right);
+        self.observe(observation);
+        (reward, self.done)
+    }
+
+    fn advance(&mut self, left: Action, right: Action) -> f32 {
+        if self.done {
+            return 0.0;
+        }
+        let dt = 1.0 / self.config.ticks_per_second as f32;
+        let mut reward = 0.0;
+        for _ in 0..self.options.frame_skip.max(1) {
+            let (round_result, _) =
+                self.pong
+                    .multi_game_round(left.keys(), right.keys(), dt, self.tick);
+            self.tick += 1;
+            match round_result {
+                RoundResult::LeftScored => reward += 1.0,
+                RoundResult::RightScored => reward -= 1.0,
+                RoundResult::None => {}
+            }
+            if self.pong.game.is_over() {
+                break;
+            }
+        }
+        self.steps += 1;
+        self.done =
+            self.pong.game.is_over() || self.options.max_steps.is_some_and(|max| self.steps >= max);
+        reward
+    }
+
+    pub fn observation(&self) -> Vec<f32> {
+        let mut observation = vec![0.0; self.options.observation_size()];
+        self.observe(&mut observation);
+        observation
+    }
+
+    // writes the observation to the start of `out`, which has to be long enough
+    pub fn observe(&self, out: &mut [f32]) {
+        let ball = &self.pong.ball;
+        let mut parts = [None; 3];
+        if self.options.ball_position {
+            parts[0] = Some(ball.get_position().to_array());
+        }
+        if self.options.ball_velocity {
+            parts[1] = Some(ball.get_velocity().to_array());
+        }
+        if self.options.paddle_positions {
+            parts[2] = Some([
+                self.pong.left_paddle.get_position().y,
+                self.pong.right_paddle.get_position().y,
+            ]);
+        }
+        for (slot, values) in out.chunks_exact_mut(2).zip(parts.into_iter().flatten()) {
+            slot.copy_from_slice(&values);
+        }
+    }
+}
+
+// Outcome of a step of all environments of a batch, observations one after another.
+#[derive(Debug, Clone, Default)]
+pub struct BatchStep {
+    // observation_size values for every environment
+    pub observations: Vec<f32>,
+    pub rewards: Vec<f32>,
+    pub dones: Vec<bool>,
+}
+
+// Many environments stepped together, for collecting experience fast.
+// An environment whose episode has ended starts a new one at its next step, seeded with
+// the next unused seed, so a batch reset with the same seed repeats itself exactly,
+// whether stepped on one thread or many.
+pub struct PongBatch {
+    options: EnvOptions,
+    envs: Vec<PongEnv>,
+    next_seed: u64,
+}
+
+impl PongBatch {
+    pub fn new(size: usize, config: Configuration, options: EnvOptions) -> Self {
+        Self {
+            options,
+            envs: (0..size).map(|_| PongEnv::new(config, options)).collect(),
+            next_seed: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    pub fn envs(&self) -> &[PongEnv] {
+        &self.envs
+    }
+
+    fn observation_size(&self) -> usize {
+        self.options.observation_size()
+    }
+
+    // environment i gets seed + i (wrapping around), returns all observations
+    pub fn reset(&mut self, seed: u64) -> Vec<f32> {
+        let size = self.observation_size();
+        let mut observations = vec![0.0; size * self.envs.len()];
+        for (i, env) in self.envs.iter_mut().enumerate() {
+            env.reset(seed.wrapping_add(i as u64));
+            env.observe(&mut observations[i * size..]);
+        }
+        self.next_seed = seed.wrapping_add(self.envs.len() as u64);
+        observations
+    }
+
+    // seeds are handed out here, in order, so that they don't depend on threads
+    fn restart_finished(&mut self) {
+        for env in &mut self.envs {
+            if env.is_done() {
+                env.reset(self.next_seed);
+                self.next_seed = self.next_seed.wrapping_add(1);
+            }
+        }
+    }
+
+    fn prepare(&mut self, actions: &[(Action, Action)]) -> BatchStep {
+        assert_eq!(
+            actions.len(),
+            self.envs.len(),
+            "one action pair per environment"
+        );
+        self.restart_finished();
+        BatchStep {
+            observations: vec![0.0; self.observation_size() * self.envs.len()],
+            rewards: vec![0.0; self.envs.len()],
+            dones: vec![false; self.envs.len()],
+        }
+    }
+
+    // (left, right) actions for every environment, stepped one after another
+    pub fn step(&mut self, actions: &[(Action, Action)]) -> BatchStep {
+        let mut result = self.prepare(actions);
+        let size = self.observation_size();
+        for ((((env, &(left, right)), observation), reward), done) in self
+            .envs
+            .iter_mut()
+            .zip(actions)
+            .zip(result.observations.chunks_mut(size))
+            .zip(&mut result.rewards)
+            .zip(&mut result.dones)
+        {
+            (*reward, *done) = env.step_into(left, right, observation);
+        }
+        result
+    }
+
+    // same as step, with the environments spread over rayon's threads
+    pub fn par_step(&mut self, actions: &[(Action, Action)]) -> BatchStep {
+        let mut result = self.prepare(actions);
+        let size = self.observation_size();
+        self.envs
+            .par_iter_mut()
+            .zip(actions)
+            .zip(result.observations.par_chunks_mut(size))
+            .zip(&mut result.rewards)
+            .zip(&mut result.dones)
+            .for_each(|((((env, &(left, right)), observation), reward), done)| {
+                (*reward, *done) = env.step_into(left, right, observation);
+            });
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // actions for a step of every environment, different for every environment and step
+    fn actions(batch: usize, step: usize) -> Vec<(Action, Action)> {
+        (0..batch)
+            .map(|i| {
+                (
+                    Action::ALL[(i + step / 7) % 3],
+                    Action::ALL[(i * 2 + step / 5) % 3],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn par_step_is_the_same_as_step() {
+        let options = EnvOptions {
+            frame_skip: 4,
+            max_steps: Some(300),
+            ..EnvOptions::default()
+        };
+        let config = Configuration::default();
+        let mut serial = PongBatch::new(6, config, options);
+        let mut parallel = PongBatch::new(6, config, options);
+        // seeds wrap around
+        let seed = u64::MAX - 2;
+        assert_eq!(serial.reset(seed), parallel.reset(seed));
+        let mut dones = 0;
+        for step in 0..1000 {
+            let actions = actions(6, step);
+            let a = serial.step(&actions);
+            let b = parallel.par_step(&actions);
+            assert_eq!(a.observations, b.observations, "step {}", step);
+            assert_eq!(a.rewards, b.rewards, "step {}", step);
+            assert_eq!(a.dones, b.dones, "step {}", step);
+            dones += a.dones.iter().filter(|&&done| done).count();
+        }
+        // episodes were restarted on the way
+        assert!(dones > 6);
+    }
+}
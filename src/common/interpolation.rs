@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use super::messages::{GameState, Tick};
+
+// how many ticks of history are kept
+const BUFFER_TICKS: Tick = 64;
+// how fast the estimate of the server clock follows new snapshots
+const CLOCK_SMOOTHING: f32 = 0.05;
+// positions aren't guessed further than that past the newest snapshot
+const MAX_EXTRAPOLATION: f32 = 0.25;
+// anything moving faster than that per tick was teleported (e.g. reset after a goal),
+// so it is snapped instead of being slid across the screen
+const TELEPORT_DISTANCE_PER_TICK: f32 = 100.0;
+
+type Position = (f32, f32);
+
+fn lerp(a: Position, b: Position, alpha: f32) -> Position {
+    (a.0 + (b.0 - a.0) * alpha, a.1 + (b.1 - a.1) * alpha)
+}
+
+// position between a and b at alpha, where b is `ticks` after a
+// alpha above 1 extrapolates
+fn blend(a: Position, b: Position, ticks: f32, alpha: f32) -> Position {
+    let distance = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+    if distance > TELEPORT_DISTANCE_PER_TICK * ticks {
+        if alpha < 1.0 {
+            a
+        } else {
+            b
+        }
+    } else {
+        lerp(a, b, alpha)
+    }
+}
+
+// positions between two states, alpha 0 is a and 1 is b, above 1 extrapolates
+pub fn blend_states(a: &GameState, b: &GameState, alpha: f32) -> GameState {
+    let ticks = (b.tick - a.tick) as f32;
+    GameState::new(
+        a.tick,
+        blend(a.ball, b.ball, ticks, alpha),
+        blend(a.left_paddle, b.left_paddle, ticks, alpha),
+        blend(a.right_paddle, b.right_paddle, ticks, alpha),
+    )
+}
+
+// Snapshots received from the server, keyed by tick.
+// Client renders `delay` in the past, so that there are usually two snapshots around
+// the rendered moment to interpolate between, even if some packets got lost.
+pub struct SnapshotBuffer {
+    snapshots: BTreeMap<Tick, GameState>,
+    tick_duration: f32,
+    delay: f32,
+    start: Instant,
+    // estimated server time minus local time, in seconds
+    clock_offset: Option<f32>,
+}
+
+impl SnapshotBuffer {
+    pub fn new(ticks_per_second: u32, delay: Duration) -> Self {
+        Self {
+            snapshots: BTreeMap::new(),
+            tick_duration: 1.0 / ticks_per_second as f32,
+            delay: delay.as_secs_f32(),
+            start: Instant::now(),
+            clock_offset: None,
+        }
+    }
+
+    fn local_time(&self, now: Instant) -> f32 {
+        now.duration_since(self.start).as_secs_f32()
+    }
+
+    pub fn insert(&mut self, game_state: GameState, now: Instant) {
+        let newest = self.newest_tick().unwrap_or(0);
+        if newest >= BUFFER_TICKS && game_state.tick < newest - BUFFER_TICKS {
+            return;
+        }
+        self.snapshots.insert(game_state.tick, game_state);
+
+        let sample = game_state.tick as f32 * self.tick_duration - self.local_time(now);
+        self.clock_offset = Some(match self.clock_offset {
+            Some(offset) => offset + (sample - offset) * CLOCK_SMOOTHING,
+            None => sample,
+        });
+
+        if let Some(newest) = self.newest_tick() {
+            if newest >= BUFFER_TICKS {
+                self.snapshots = self.snapshots.split_off(&(newest - BUFFER_TICKS));
+            }
+        }
+    }
+
+    // forgets the estimate of the server clock, e.g. after the server stopped for a while,
+    // next snapshot sets it anew
+    pub fn resync(&mut self) {
+        self.clock_offset = None;
+    }
+
+    pub fn newest_tick(&self) -> Option<Tick> {
+        self.snapshots.keys().next_back().copied()
+    }
+
+    // tick (with fraction) which should be rendered now
+    pub fn render_tick(&self, now: Instant) -> Option<f32> {
+        let offset = self.clock_offset?;
+        let server_time = self.local_time(now) + offset - self.delay;
+        Some((server_time / self.tick_duration).max(0.0))
+    }
+
+    // Positions at the render time, interpolated between the snapshots around it,
+    // or extrapolated from the two newest ones if nothing newer arrived yet.
+    pub fn sample(&self, now: Instant) -> Option<GameState> {
+        let render_tick = self.render_tick(now)?;
+        let before = self
+            .snapshots
+            .range(..=render_tick.floor() as Tick)
+            .next_back();
+        let after = self
+            .snapshots
+            .range(render_tick.floor() as Tick + 1..)
+            .next();
+
+        let (a, b, alpha) = match (before, after) {
+            (Some((_, a)), Some((_, b))) => {
+                let alpha = (render_tick - a.tick as f32) / (b.tick - a.tick) as f32;
+                (a, b, alpha)
+            }
+            (Some((&newest, b)), None) => {
+                // b is the newest snapshot, extrapolate using the one before it
+                let Some((_, a)) = self.snapshots.range(..newest).next_back() else {
+                    return Some(*b);
+                };
+                let ahead =
+                    (render_tick - b.tick as f32).min(MAX_EXTRAPOLATION / self.tick_duration);
+                let alpha = 1.0 + ahead / (b.tick - a.tick) as f32;
+                (a, b, alpha)
+            }
+            (None, Some((_, b))) => return Some(*b),
+            (None, None) => return None,
+        };
+        Some(GameState {
+            tick: render_tick as Tick,
+            ..blend_states(a, b, alpha)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a quarter of a second per tick and half a second of delay keep every time exact
+    const TICKS_PER_SECOND: u32 = 4;
+    const DELAY: Duration = Duration::from_millis(500);
+
+    // everything moves steadily, so the right position at any tick is known
+    fn state(tick: Tick) -> GameState {
+        let t = tick as f32;
+        GameState::new(
+            tick,
+            (100.0 + 10.0 * t, 50.0 + 5.0 * t),
+            (10.0, 300.0 - 4.0 * t),
+            (790.0, 200.0 + 2.0 * t),
+        )
+    }
+
+    fn ball_at(tick: f32) -> Position {
+        (100.0 + 10.0 * tick, 50.0 + 5.0 * tick)
+    }
+
+    // the moment a snapshot of that tick arrives right on time
+    fn at(buffer: &SnapshotBuffer, tick: f32) -> Instant {
+        buffer.start + Duration::from_secs_f32(tick / TICKS_PER_SECOND as f32)
+    }
+
+    // buffer with the snapshots of those ticks, each arriving right on time
+    fn buffer_with(ticks: &[Tick]) -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::new(TICKS_PER_SECOND, DELAY);
+        for &tick in ticks {
+            buffer.insert(state(tick), at(&buffer, tick as f32));
+        }
+        buffer
+    }
+
+    // what is rendered at the moment the server is at `tick`, two ticks of delay later
+    fn sample_at(buffer: &SnapshotBuffer, tick: f32) -> GameState {
+        buffer.sample(at(buffer, tick)).unwrap()
+    }
+
+    #[test]
+    fn renders_delay_in_the_past() {
+        let buffer = buffer_with(&[0, 1, 2]);
+        assert_eq!(buffer.render_tick(at(&buffer, 2.0)), Some(0.0));
+        assert_eq!(buffer.render_tick(at(&buffer, 3.5)), Some(1.5));
+        // nothing before the first tick
+        assert_eq!(buffer.render_tick(at(&buffer, 1.0)), Some(0.0));
+        assert_eq!(
+            SnapshotBuffer::new(TICKS_PER_SECOND, DELAY).render_tick(Instant::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn interpolates_between_snapshots_around_render_time() {
+        // odd ticks got lost
+        let buffer = buffer_with(&[0, 2, 4, 6, 8]);
+        // render tick 3, halfway between 2 and 4
+        let sampled = sample_at(&buffer, 5.0);
+        assert_eq!(sampled.tick, 3);
+        assert_eq!(sampled.ball, ball_at(3.0));
+        assert_eq!(sampled.left_paddle, (10.0, 288.0));
+        assert_eq!(sampled.right_paddle, (790.0, 206.0));
+        // render tick 4.5, a quarter of the way from 4 to 6
+        let sampled = sample_at(&buffer, 6.5);
+        assert_eq!(sampled.tick, 4);
+        assert_eq!(sampled.ball, ball_at(4.5));
+        // right at a snapshot
+        assert_eq!(sample_at(&buffer, 8.0).ball, ball_at(6.0));
+    }
+
+    #[test]
+    fn teleport_is_snapped_to_not_slid() {
+        let mut buffer = buffer_with(&[0, 1]);
+        // ball reset to the middle after a goal
+        let reset = GameState {
+            ball: (400.0, 300.0),
+            ..state(2)
+        };
+        buffer.insert(reset, at(&buffer, 2.0));
+        assert_eq!(sample_at(&buffer, 3.9).ball, ball_at(1.0));
+        assert_eq!(sample_at(&buffer, 4.0).ball, (400.0, 300.0));
+        // paddles still slide
+        assert_eq!(sample_at(&buffer, 3.5).left_paddle, (10.0, 294.0));
+    }
+
+    #[test]
+    fn out_of_order_and_repeated_snapshots() {
+        let mut buffer = SnapshotBuffer::new(TICKS_PER_SECOND, DELAY);
+        // 3 arrives before 2, 1 comes twice, 2 comes again long after
+        for (tick, arrived) in [(0, 0.0), (1, 1.0), (3, 3.0), (2, 3.0), (1, 3.5), (4, 4.0)] {
+            buffer.insert(state(tick), at(&buffer, arrived));
+        }
+        buffer.insert(state(2), at(&buffer, 5.0));
+        assert_eq!(
+            buffer.snapshots.keys().copied().collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        // late snapshots pull the clock back a little, but whatever tick is rendered
+        // it's between the right two snapshots
+        for now in [3.0, 4.25, 5.0, 5.5] {
+            let render_tick = buffer.render_tick(at(&buffer, now)).unwrap();
+            assert!(render_tick < 4.0);
+            let (x, y) = sample_at(&buffer, now).ball;
+            let (expected_x, expected_y) = ball_at(render_tick);
+            assert!(
+                (x - expected_x).abs() < 1e-3 && (y - expected_y).abs() < 1e-3,
+                "at {}: {:?} instead of {:?}",
+                render_tick,
+                (x, y),
+                (expected_x, expected_y)
+            );
+        }
+    }
+
+    #[test]
+    fn snapshots_older_than_the_buffer_are_dropped() {
+        let mut buffer = buffer_with(&(0..=100).collect::<Vec<_>>());
+        assert_eq!(buffer.snapshots.len(), BUFFER_TICKS as usize + 1);
+        assert_eq!(buffer.snapshots.keys().next(), Some(&(100 - BUFFER_TICKS)));
+        buffer.insert(state(10), at(&buffer, 100.0));
+        assert!(!buffer.snapshots.contains_key(&10));
+        assert_eq!(buffer.newest_tick(), Some(100));
+    }
+
+    #[test]
+    fn extrapolates_from_the_two_newest_up_to_the_cap() {
+        let buffer = buffer_with(&[0, 1, 2]);
+        // render tick 2.5, half a tick past the newest snapshot
+        let sampled = sample_at(&buffer, 4.5);
+        assert_eq!(sampled.tick, 2);
+        assert_eq!(sampled.ball, ball_at(2.5));
+        assert_eq!(sampled.left_paddle, (10.0, 290.0));
+        // MAX_EXTRAPOLATION is one tick here, so nothing is guessed past tick 3
+        let cap = MAX_EXTRAPOLATION * TICKS_PER_SECOND as f32;
+        assert_eq!(cap, 1.0);
+        assert_eq!(sample_at(&buffer, 5.0).ball, ball_at(3.0));
+        for later in [6.0, 10.0, 100.0] {
+            let sampled = sample_at(&buffer, later);
+            assert_eq!(sampled.tick, later as Tick - 2);
+            assert_eq!(sampled.ball, ball_at(3.0));
+        }
+    }
+
+    #[test]
+    fn single_snapshot_is_shown_as_it_is() {
+        let buffer = buffer_with(&[5]);
+        for now in [0.0, 5.0, 7.0, 20.0] {
+            assert_eq!(buffer.sample(at(&buffer, now)), Some(state(5)));
+        }
+        assert_eq!(
+            SnapshotBuffer::new(TICKS_PER_SECOND, DELAY).sample(Instant::now()),
+            None
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::time::{Duration, Instant};
+
+use super::messages::PlayerId;
+
+// rating difference accepted right after joining the queue
+const INITIAL_RATING_GAP: f64 = 100.0;
+// how much the accepted difference grows for every second of waiting
+const RATING_GAP_GROWTH: f64 = 25.0;
+
+struct Queued {
+    player_id: PlayerId,
+    rating: f64,
+    since: Instant,
+}
+
+// rating difference the player accepts after waiting that long
+fn accepted_gap(waited: Duration) -> f64 {
+    INITIAL_RATING_GAP + RATING_GAP_GROWTH * waited.as_secs_f64()
+}
+
+// Players waiting for a QuickMatch opponent.
+// Pairs players of similar rating, the longer someone waits, the bigger difference
+// it accepts, so that eventually everyone gets a game.
+#[derive(Default)]
+pub struct MatchmakingQueue {
+    // oldest first
+    queue: Vec<Queued>,
+}
+
+impl MatchmakingQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, player_id: PlayerId, rating: f64) {
+        self.push_at(player_id, rating, Instant::now());
+    }
+
+    fn push_at(&mut self, player_id: PlayerId, rating: f64, since: Instant) {
+        self.queue.push(Queued {
+            player_id,
+            rating,
+            since,
+        });
+    }
+
+    pub fn remove(&mut self, player_id: PlayerId) {
+        self.queue.retain(|queued| queued.player_id != player_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // takes out players who have waited longer than that, oldest first
+    pub fn take_waiting_longer_than(&mut self, wait: Duration) -> Vec<PlayerId> {
+        self.take_waiting_longer_than_at(wait, Instant::now())
+    }
+
+    fn take_waiting_longer_than_at(&mut self, wait: Duration, now: Instant) -> Vec<PlayerId> {
+        let (waited, rest) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|queued| now.saturating_duration_since(queued.since) > wait);
+        self.queue = rest;
+        waited
+            .into_iter()
+            .map(|queued: Queued| queued.player_id)
+            .collect()
+    }
+
+    // Takes out pairs that can play now, longest waiting player of a pair first.
+    // Each player, oldest first, gets the closest rated opponent within the gap it accepts.
+    pub fn pair(&mut self) -> Vec<(PlayerId, PlayerId)> {
+        self.pair_at(Instant::now())
+    }
+
+    fn pair_at(&mut self, now: Instant) -> Vec<(PlayerId, PlayerId)> {
+        let mut pairs = Vec::new();
+        let mut i = 0;
+        while i < self.queue.len() {
+            let gap = accepted_gap(now.saturating_duration_since(self.queue[i].since));
+            let rating = self.queue[i].rating;
+            let opponent = (i + 1..self.queue.len())
+                .map(|j| (j, (self.queue[j].rating - rating).abs()))
+                .filter(|&(_, difference)| difference <= gap)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(j, _)| j);
+            match opponent {
+                Some(j) => {
+                    let second = self.queue.remove(j);
+                    let first = self.queue.remove(i);
+                    pairs.push((first.player_id, second.player_id));
+                }
+                None => i += 1,
+            }
+        }
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    // players joined at `start + joined` seconds
+    fn queue_of(start: Instant, players: &[(PlayerId, f64, u64)]) -> MatchmakingQueue {
+        let mut queue = MatchmakingQueue::new();
+        for &(player_id, rating, joined) in players {
+            queue.push_at(player_id, rating, start + secs(joined));
+        }
+        queue
+    }
+
+    #[test]
+    fn closest_opponent_within_the_gap() {
+        let start = Instant::now();
+        // 1 is oldest, 2 and 3 are both within 100, 3 is closer, 4 is closest but too far
+        let mut queue = queue_of(
+            start,
+            &[
+                (1, 1500.0, 0),
+                (2, 1580.0, 0),
+                (3, 1450.0, 0),
+                (4, 1300.0, 0),
+            ],
+        );
+        assert_eq!(queue.pair_at(start), vec![(1, 3)]);
+        assert_eq!(queue.len(), 2);
+        // 2 and 4 are 280 apart, accepted after (280 - 100) / 25 = 7.2 s
+        assert!(queue.pair_at(start + secs(7)).is_empty());
+        assert_eq!(queue.pair_at(start + secs(8)), vec![(2, 4)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn gap_widens_with_waiting() {
+        let start = Instant::now();
+        let mut queue = queue_of(start, &[(1, 1500.0, 0), (2, 1750.0, 0)]);
+        // 250 apart, accepted after (250 - 100) / 25 = 6 s
+        for waited in [0, 1, 5] {
+            assert!(
+                queue.pair_at(start + secs(waited)).is_empty(),
+                "after {} s",
+                waited
+            );
+        }
+        assert_eq!(queue.pair_at(start + secs(6)), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn gap_is_the_older_players() {
+        let start = Instant::now();
+        // at 8 s 1 accepts 300 but 2, who came at 7 s, only 125, the older player's gap decides
+        let mut queue = queue_of(start, &[(1, 1500.0, 0), (2, 1800.0, 7)]);
+        assert!(queue.pair_at(start + secs(7)).is_empty());
+        assert_eq!(queue.pair_at(start + secs(8)), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn oldest_player_chooses_first() {
+        let start = Instant::now();
+        // 3 is closest to 2, but 1 has waited longer and takes it
+        let mut queue = queue_of(
+            start,
+            &[
+                (1, 1500.0, 0),
+                (2, 1560.0, 1),
+                (3, 1550.0, 2),
+                (4, 1720.0, 3),
+            ],
+        );
+        assert_eq!(queue.pair_at(start + secs(3)), vec![(1, 3)]);
+        // 2 and 4 are 160 apart, 2 accepts that after 2.4 s of waiting
+        let mut queue = queue_of(
+            start,
+            &[
+                (1, 1500.0, 0),
+                (2, 1560.0, 1),
+                (3, 1550.0, 2),
+                (4, 1720.0, 3),
+            ],
+        );
+        assert_eq!(queue.pair_at(start + secs(4)), vec![(1, 3), (2, 4)]);
+    }
+
+    #[test]
+    fn removed_player_isnt_paired() {
+        let start = Instant::now();
+        let mut queue = queue_of(start, &[(1, 1500.0, 0), (2, 1500.0, 0), (3, 1500.0, 0)]);
+        queue.remove(2);
+        assert_eq!(queue.pair_at(start), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn long_waiting_players_are_taken_out_oldest_first() {
+        let start = Instant::now();
+        let mut queue = queue_of(
+            start,
+            &[
+                (1, 1500.0, 0),
+                (2, 2500.0, 3),
+                (3, 500.0, 5),
+                (4, 1000.0, 9),
+            ],
+        );
+        assert!(queue
+            .take_waiting_longer_than_at(secs(10), start + secs(10))
+            .is_empty());
+        assert_eq!(
+            queue.take_waiting_longer_than_at(secs(5), start + secs(10)),
+            vec![1, 2]
+        );
+        assert_eq!(queue.len(), 2);
+        assert_eq!(
+            queue.take_waiting_longer_than_at(secs(0), start + secs(10)),
+            vec![3, 4]
+        );
+        assert!(queue.is_empty());
+    }
+}
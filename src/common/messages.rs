@@ -1,10 +1,31 @@
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
 use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use crate::configuration::FromConfiguration;
+use super::auth::{self, PasswordHash, Signature};
+use super::codec::{self, DecodeError};
+use crate::configuration::{Configuration, FromConfiguration};
+pub use crate::rules::Side;
 
 pub type Tick = u32;
 pub type PlayerId = u32;
+// games are numbered by the server, ids are never reused
+pub type GameId = u32;
+// secret handed to the player on joining, lets it come back to its game from another address,
+// and signs its packets (see auth.rs)
+pub type SessionToken = u64;
+
+// how often players let the server know they are still there
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// player gives up on a server that hasn't said anything for that long
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+// silence after which the connection is considered lost: server pauses the game,
+// player starts trying to rejoin
+pub const CONNECTION_LOST_AFTER: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct GameState {
@@ -12,6 +33,9 @@ pub struct GameState {
     pub ball: (f32, f32),
     pub left_paddle: (f32, f32),
     pub right_paddle: (f32, f32),
+    // newest input tick of each player the server has processed by this tick
+    pub left_input_tick: Tick,
+    pub right_input_tick: Tick,
 }
 
 impl GameState {
@@ -26,15 +50,25 @@ impl GameState {
             ball,
             left_paddle,
             right_paddle,
+            left_input_tick: 0,
+            right_input_tick: 0,
         }
     }
 
+    pub fn with_input_ticks(mut self, left: Tick, right: Tick) -> Self {
+        self.left_input_tick = left;
+        self.right_input_tick = right;
+        self
+    }
+
     pub fn update(&mut self, gs: GameState) {
         if gs.tick > self.tick {
             self.tick = gs.tick;
             self.ball = gs.ball;
             self.left_paddle = gs.left_paddle;
             self.right_paddle = gs.right_paddle;
+            self.left_input_tick = gs.left_input_tick;
+            self.right_input_tick = gs.right_input_tick;
         }
     }
 }
@@ -46,6 +80,8 @@ impl Default for GameState {
             ball: (0.0, 0.0),
             left_paddle: (0.0, 0.0),
             right_paddle: (0.0, 0.0),
+            left_input_tick: 0,
+            right_input_tick: 0,
         }
     }
 }
@@ -60,85 +96,643 @@ impl FromConfiguration for GameState {
                 config.screen_width - config.paddle_width / 2.0,
                 config.screen_height / 2.0,
             ),
+            left_input_tick: 0,
+            right_input_tick: 0,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+// keys held by the player during one of its ticks, sent every tick
 pub struct PlayerMove {
     pub player_id: PlayerId,
     pub tick: Tick,
-    pub dir: Direction,
+    pub up: bool,
+    pub down: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
 pub enum Message {
     // sent by server
-    Ok(Side, PlayerId),
-    Taken,
+    // player is in, side is known only once the game starts
+    Ok(PlayerId, SessionToken),
+    // code of the room the player sits in, follows Ok
+    InRoom(RoomCode),
+    Refused(Refusal),
     State(GameState),
-    Ready,
+    // game starts, player plays on that side (None for spectators),
+    // server simulates with that configuration
+    Ready(Option<Side>, Configuration),
     Score(u32, u32),
+    // the match is decided, the game stands still until the players leave
+    MatchOver {
+        winner: Side,
+        score: (u32, u32),
+    },
+    // sent every tick instead of State while the game is stopped
+    Paused(Pause),
+    // answer to ListGames(from): games from that position on, as many as fit in a page,
+    // and how many there are in all
+    GamesPage {
+        from: u32,
+        games: [Option<GameListing>; GAMES_PER_PAGE],
+        total: u32,
+    },
     // sent by both
     EndingGame(PlayerId),
+    // keepalive, sent by player every HEARTBEAT_INTERVAL, server echoes it back
+    Heartbeat(PlayerId),
     // sent by client
-    Join,
+    // play with an opponent of similar rating, rated only if the name is given,
+    // with the hashed secret the name belongs to
+    QuickMatch(Option<(PlayerName, PasswordHash)>),
+    // private game, joined only by code (and password, if given)
+    CreateRoom(Option<PasswordHash>),
+    JoinRoom(RoomCode, Option<PasswordHash>),
+    // comes back to the game of the session, possibly from a new address
+    Rejoin(SessionToken),
+    // watch a running game, the spectator gets State and Score but doesn't play
+    Spectate(GameId),
+    // running games which can be watched, a page of them from that position on
+    ListGames(u32),
     Move(PlayerMove),
 }
 
+impl Message {
+    // sent before the player knows its session token, so they can't be signed
+    pub fn is_join_request(&self) -> bool {
+        matches!(
+            self,
+            Message::QuickMatch(_)
+                | Message::CreateRoom(_)
+                | Message::JoinRoom(..)
+                | Message::Rejoin(_)
+                | Message::Spectate(_)
+        )
+    }
+}
+
+// why the server didn't let the player in
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
-pub enum Side {
-    Left,
-    Right,
+pub enum Refusal {
+    NoSuchRoom,
+    WrongPassword,
+    RoomFull,
+    UnknownSession,
+    NoSuchGame,
+    // someone else plays under the name, with another secret
+    NameClaimed,
+    // a player with the name is already queued or playing
+    NameInUse,
 }
 
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Refusal::NoSuchRoom => write!(f, "there is no room with this code"),
+            Refusal::WrongPassword => write!(f, "wrong password"),
+            Refusal::RoomFull => write!(f, "the room is full"),
+            Refusal::UnknownSession => {
+                write!(
+                    f,
+                    "server doesn't know this session, the game might be over"
+                )
+            }
+            Refusal::NoSuchGame => write!(f, "there is no running public game with this id"),
+            Refusal::NameClaimed => write!(f, "the name belongs to someone else, wrong secret"),
+            Refusal::NameInUse => write!(f, "someone with this name is already playing"),
+        }
+    }
+}
+
+// games in a GamesPage, a single datagram answers a single ListGames
+// (few, as Message is Copy and every variant is as big as the biggest)
+pub const GAMES_PER_PAGE: usize = 4;
+
+// running game as listed for spectators
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
-pub enum Direction {
-    Up(bool),
-    Down(bool),
+pub struct GameListing {
+    pub game_id: GameId,
+    // names of named players
+    pub left: Option<PlayerName>,
+    pub right: Option<PlayerName>,
+    pub spectators: u32,
+}
+
+const ROOM_CODE_LEN: usize = 5;
+// no 0/O or 1/I, so that codes are easy to read out and type
+const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+// short code the opponent uses to join a room, e.g. K7QXM
+// travels as a string, so that it's readable in json
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct RoomCode([u8; ROOM_CODE_LEN]);
+
+impl RoomCode {
+    pub fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut code = [0; ROOM_CODE_LEN];
+        for c in code.iter_mut() {
+            *c = ROOM_CODE_ALPHABET[rng.gen_range(0..ROOM_CODE_ALPHABET.len())];
+        }
+        Self(code)
+    }
+}
+
+impl fmt::Display for RoomCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // only ever made of ROOM_CODE_ALPHABET
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl From<RoomCode> for String {
+    fn from(code: RoomCode) -> Self {
+        code.to_string()
+    }
+}
+
+impl TryFrom<String> for RoomCode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::str::FromStr for RoomCode {
+    type Err = String;
+
+    // case doesn't matter
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_uppercase();
+        let bytes: [u8; ROOM_CODE_LEN] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| format!("room code has {} characters", ROOM_CODE_LEN))?;
+        if let Some(&c) = bytes.iter().find(|c| !ROOM_CODE_ALPHABET.contains(c)) {
+            return Err(format!("'{}' can't be a part of a room code", c as char));
+        }
+        Ok(Self(bytes))
+    }
+}
+
+const PLAYER_NAME_MAX_LEN: usize = 16;
+
+// name the server keeps the player's rating under
+// travels as a string, stored padded with zeros so that Message stays Copy
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct PlayerName([u8; PLAYER_NAME_MAX_LEN]);
+
+impl PlayerName {
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|&c| c == 0).unwrap_or(self.0.len());
+        // only ever made of ascii
+        std::str::from_utf8(&self.0[..len]).unwrap()
+    }
+}
+
+impl fmt::Display for PlayerName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<PlayerName> for String {
+    fn from(name: PlayerName) -> Self {
+        name.to_string()
+    }
+}
+
+impl TryFrom<String> for PlayerName {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::str::FromStr for PlayerName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.len() > PLAYER_NAME_MAX_LEN {
+            return Err(format!(
+                "name has to have 1 to {} characters",
+                PLAYER_NAME_MAX_LEN
+            ));
+        }
+        if let Some(c) = s
+            .chars()
+            .find(|&c| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+        {
+            return Err(format!("'{}' can't be a part of a name", c));
+        }
+        let mut bytes = [0; PLAYER_NAME_MAX_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Self(bytes))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Pause {
+    // player lost connection, the game is forfeited unless it is back within that many seconds
+    WaitingFor(PlayerId, u32),
+    // everyone is back, game resumes in that many seconds
+    Resuming(u32),
+}
+
+pub type Seq = u32;
+// picked at random by the sender of reliable messages, so that the receiver notices
+// when the sender started over (e.g. it was restarted) and numbering begins anew
+pub type StreamId = u32;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Packet {
+    // may get lost or come out of order, for messages sent all the time (State, Move...)
+    Unreliable(Message),
+    // resent until acknowledged, delivered once and in order
+    Reliable(StreamId, Seq, Message),
+    Ack(StreamId, Seq),
+}
+
+// what actually travels in a datagram
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub struct Datagram {
+    pub packet: Packet,
+    // present in everything sent by a player who already joined
+    pub signature: Option<Signature>,
 }
 
 pub enum ReadType {
     AllRead,
     WrongRead,
     MessageRead(Message, std::net::SocketAddr),
+    // peer speaks the given protocol version, which is not ours
+    VersionMismatch(u8, std::net::SocketAddr),
 }
 
-pub fn get_message(socket: &UdpSocket) -> ReadType {
-    let mut buf = [0; 1024];
-    let (amt, who) = match socket.recv_from(&mut buf) {
-        Ok((amt, who)) => (amt, who),
-        Err(_) => {
-            // Timeout
-            return ReadType::AllRead;
+// biggest possible UDP payload
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+// Reads the next message, reliable ones come in order and only once.
+// Acks and reliable messages which have to wait for earlier ones are handled
+// by the channel and not returned.
+pub fn get_message(socket: &UdpSocket, channel: &ReliableChannel) -> ReadType {
+    if let Some((msg, who)) = channel.next_delivered() {
+        return ReadType::MessageRead(msg, who);
+    }
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
+    loop {
+        let (amt, who) = match socket.recv_from(&mut buf) {
+            Ok((amt, who)) => (amt, who),
+            Err(_) => {
+                // Timeout
+                return ReadType::AllRead;
+            }
+        };
+
+        let datagram = match codec::decode(&buf[..amt]) {
+            Ok(datagram) => datagram,
+            Err(e) => return read_error(socket, e, who),
+        };
+        if !channel.authentic(&datagram, &who) {
+            continue;
         }
-    };
+        match datagram.packet {
+            Packet::Unreliable(msg) => return ReadType::MessageRead(msg, who),
+            Packet::Reliable(stream, seq, msg) => {
+                channel.received(socket, stream, seq, msg, who);
+                if let Some((msg, who)) = channel.next_delivered() {
+                    return ReadType::MessageRead(msg, who);
+                }
+            }
+            Packet::Ack(stream, seq) => channel.acked(stream, seq, who),
+        }
+    }
+}
 
-    match serde_json::from_slice(buf[..amt].as_ref()) {
-        Ok(msg) => ReadType::MessageRead(msg, who),
-        Err(e) => {
-            eprintln!("Error: {:?}", e);
-            eprintln!(
-                "Coudn't read message: {:?}",
-                String::from_utf8_lossy(&buf[..amt])
-            );
+fn read_error(socket: &UdpSocket, error: DecodeError, who: SocketAddr) -> ReadType {
+    match error {
+        DecodeError::NoMagic => ReadType::WrongRead,
+        DecodeError::OtherVersion(version) => {
+            // tell them, they can't understand anything else we send
+            let _ = socket.send_to(&codec::encode_version_mismatch(), who);
+            ReadType::VersionMismatch(version, who)
+        }
+        DecodeError::VersionMismatch(version) => ReadType::VersionMismatch(version, who),
+        DecodeError::Malformed(e) => {
+            eprintln!("Coudn't read message from {}: {}", who, e);
             ReadType::WrongRead
         }
     }
 }
 
-pub fn send_message(socket: &UdpSocket, msg: &Message, who: &SocketAddr) {
-    let response = serde_json::to_string(msg).unwrap();
+fn send_packet(socket: &UdpSocket, packet: &Packet, who: &SocketAddr) {
+    let datagram = Datagram {
+        packet: *packet,
+        signature: auth::sign_next(packet),
+    };
     socket
-        .send_to(response.as_bytes(), who)
+        .send_to(&codec::encode(&datagram), who)
         .expect("Couldn't send response");
 }
 
+// unreliable, for anything sent often enough that a lost one doesn't matter
+pub fn send_message(socket: &UdpSocket, msg: &Message, who: &SocketAddr) {
+    send_packet(socket, &Packet::Unreliable(*msg), who);
+}
+
+// unacknowledged reliable message is sent again after that long
+pub const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(200);
+// peer which didn't acknowledge that many sends of a message is given up on
+const MAX_TRANSMISSIONS: u32 = 25;
+// messages further ahead of the next expected one are dropped, the sender sends them again
+const EARLY_WINDOW: Seq = 64;
+// state of a peer without a session is dropped after that long without traffic
+const ANONYMOUS_PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Outgoing {
+    seq: Seq,
+    msg: Message,
+    last_sent: Instant,
+    transmissions: u32,
+}
+
+struct Peer {
+    // sending side
+    stream: StreamId,
+    next_seq: Seq,
+    unacked: VecDeque<Outgoing>,
+    // receiving side
+    incoming_stream: Option<StreamId>,
+    next_expected: Seq,
+    // arrived before some earlier message
+    early: BTreeMap<Seq, Message>,
+    // forgotten as soon as everything sent to it is acknowledged
+    closing: bool,
+    // last time anything was sent to it or came from it
+    last_active: Instant,
+}
+
+impl Peer {
+    fn new() -> Self {
+        Self {
+            stream: rand::random(),
+            next_seq: 0,
+            unacked: VecDeque::new(),
+            incoming_stream: None,
+            next_expected: 0,
+            early: BTreeMap::new(),
+            closing: false,
+            last_active: Instant::now(),
+        }
+    }
+
+    // Drops what we were sending, the next message starts a new stream numbered from 0,
+    // as the peer would wait forever for the dropped numbers otherwise.
+    // What comes from the peer is kept, its stream goes on.
+    fn give_up(&mut self) {
+        self.unacked.clear();
+        self.stream = rand::random();
+        self.next_seq = 0;
+    }
+}
+
+struct Credentials {
+    player_id: PlayerId,
+    token: SessionToken,
+    // counter of the newest packet accepted from the player, see auth.rs
+    newest: Option<u64>,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    peers: HashMap<SocketAddr, Peer>,
+    // in order, waiting to be returned by get_message
+    delivered: VecDeque<(Message, SocketAddr)>,
+    // player expected at the address, with the token its packets have to be signed with
+    credentials: HashMap<SocketAddr, Credentials>,
+    // packets dropped for a missing or wrong signature, since last asked
+    rejected: u64,
+}
+
+// Reliability layer for control messages (Ready, Score, MatchOver, EndingGame) on top of the UDP socket.
+// Every peer gets its own numbering, messages are sent again every RETRANSMIT_INTERVAL
+// until the peer acknowledges them, and the receiver delivers them in order, dropping duplicates.
+// It is shared by all threads using the socket, so that acks read by one of them
+// stop retransmissions of messages sent by another.
+#[derive(Default)]
+pub struct ReliableChannel {
+    state: Mutex<ChannelState>,
+}
+
+impl ReliableChannel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn send(&self, socket: &UdpSocket, msg: &Message, who: &SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        let peer = state.peers.entry(*who).or_insert_with(Peer::new);
+        peer.last_active = Instant::now();
+        let seq = peer.next_seq;
+        peer.next_seq += 1;
+        peer.unacked.push_back(Outgoing {
+            seq,
+            msg: *msg,
+            last_sent: Instant::now(),
+            transmissions: 1,
+        });
+        send_packet(socket, &Packet::Reliable(peer.stream, seq, *msg), who);
+    }
+
+    // Sends again what wasn't acknowledged in time, should be called regularly
+    // Also forgets peers without a session which went quiet, so that datagrams from
+    // random addresses don't pile up.
+    pub fn resend(&self, socket: &UdpSocket) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let credentials = &state.credentials;
+        state.peers.retain(|who, peer| {
+            if !credentials.contains_key(who)
+                && peer.unacked.is_empty()
+                && peer.last_active.elapsed() >= ANONYMOUS_PEER_TIMEOUT
+            {
+                return false;
+            }
+            if peer.unacked.iter().any(|outgoing| {
+                outgoing.transmissions >= MAX_TRANSMISSIONS
+                    && outgoing.last_sent.elapsed() >= RETRANSMIT_INTERVAL
+            }) {
+                println!("Giving up on reliable messages to {}", who);
+                peer.give_up();
+            }
+            for outgoing in peer.unacked.iter_mut() {
+                if outgoing.last_sent.elapsed() < RETRANSMIT_INTERVAL {
+                    continue;
+                }
+                outgoing.last_sent = Instant::now();
+                outgoing.transmissions += 1;
+                let packet = Packet::Reliable(peer.stream, outgoing.seq, outgoing.msg);
+                send_packet(socket, &packet, who);
+            }
+            !(peer.closing && peer.unacked.is_empty())
+        });
+    }
+
+    // whether something sent to who still waits for acknowledgement
+    pub fn pending(&self, who: &SocketAddr) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .peers
+            .get(who)
+            .is_some_and(|peer| !peer.unacked.is_empty())
+    }
+
+    // peer is gone, its state is dropped once the messages already sent to it are delivered
+    pub fn close(&self, who: &SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        state.credentials.remove(who);
+        if let Some(peer) = state.peers.get_mut(who) {
+            peer.closing = true;
+        }
+    }
+
+    // drops the state right away, the next message starts a new stream
+    pub fn forget(&self, who: &SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        state.peers.remove(who);
+        state.credentials.remove(who);
+    }
+
+    // From now on packets from who are dropped, unless signed by the player with the token.
+    // A player moving to a new address keeps its counter, so that what it sent from the old
+    // one can't be replayed from the new one: close the old address only after this.
+    pub fn authenticate(&self, who: &SocketAddr, player_id: PlayerId, token: SessionToken) {
+        let mut state = self.state.lock().unwrap();
+        let newest = state
+            .credentials
+            .values()
+            .filter(|credentials| credentials.player_id == player_id)
+            .map(|credentials| credentials.newest)
+            .max()
+            .flatten();
+        state.credentials.insert(
+            *who,
+            Credentials {
+                player_id,
+                token,
+                newest,
+            },
+        );
+    }
+
+    // number of packets dropped by authentication since the last call
+    pub fn take_rejected(&self) -> u64 {
+        std::mem::take(&mut self.state.lock().unwrap().rejected)
+    }
+
+    fn authentic(&self, datagram: &Datagram, who: &SocketAddr) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(credentials) = state.credentials.get_mut(who) else {
+            return true;
+        };
+        // player repeating it doesn't know the token yet
+        if let Packet::Unreliable(msg) = datagram.packet {
+            if msg.is_join_request() {
+                return true;
+            }
+        }
+        // replayed or overtaken by a newer packet
+        let valid = datagram.signature.is_some_and(|signature| {
+            signature.player_id == credentials.player_id
+                && Some(signature.counter) > credentials.newest
+                && auth::verify(&datagram.packet, &signature, credentials.token)
+        });
+        if valid {
+            credentials.newest = datagram.signature.map(|signature| signature.counter);
+        } else {
+            state.rejected += 1;
+        }
+        valid
+    }
+
+    fn next_delivered(&self) -> Option<(Message, SocketAddr)> {
+        self.state.lock().unwrap().delivered.pop_front()
+    }
+
+    fn received(
+        &self,
+        socket: &UdpSocket,
+        stream: StreamId,
+        seq: Seq,
+        msg: Message,
+        who: SocketAddr,
+    ) {
+        // acked even if it's a duplicate, as our previous ack might have been lost
+        send_packet(socket, &Packet::Ack(stream, seq), &who);
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        // a peer we know nothing about (or forgot while it was quiet) may be in the middle
+        // of its stream, what it sent before was delivered, so it's followed from here
+        let peer = state.peers.entry(who).or_insert_with(|| Peer {
+            incoming_stream: Some(stream),
+            next_expected: seq,
+            ..Peer::new()
+        });
+        peer.last_active = Instant::now();
+        if peer.incoming_stream != Some(stream) {
+            peer.incoming_stream = Some(stream);
+            peer.next_expected = 0;
+            peer.early.clear();
+        }
+        if seq < peer.next_expected || seq - peer.next_expected > EARLY_WINDOW {
+            return;
+        }
+        peer.early.insert(seq, msg);
+        while let Some(msg) = peer.early.remove(&peer.next_expected) {
+            state.delivered.push_back((msg, who));
+            peer.next_expected += 1;
+        }
+    }
+
+    fn acked(&self, stream: StreamId, seq: Seq, who: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(peer) = state.peers.get_mut(&who) {
+            if peer.stream == stream {
+                peer.last_active = Instant::now();
+                peer.unacked.retain(|outgoing| outgoing.seq != seq);
+            }
+        }
+    }
+
+    // Waits until everything sent to who is acknowledged, or the timeout passes,
+    // e.g. before exiting. Socket should be non-blocking or have a read timeout
+    pub fn flush(&self, socket: &UdpSocket, who: &SocketAddr, timeout: Duration) {
+        let start = Instant::now();
+        while self.pending(who) && start.elapsed() < timeout {
+            if let ReadType::AllRead = get_message(socket, self) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            self.resend(socket);
+        }
+    }
+}
+
 // Wait for a message from a specific address
 // Socket should be in blocking mode
-pub fn wait_for_message(socket: &UdpSocket, who: &SocketAddr, msg: Message) {
+pub fn wait_for_message(
+    socket: &UdpSocket,
+    channel: &ReliableChannel,
+    who: &SocketAddr,
+    msg: Message,
+) {
     loop {
-        match get_message(socket) {
+        match get_message(socket, channel) {
             ReadType::MessageRead(msg_recv, addr) => {
                 if addr == *who && msg == msg_recv {
                     break;
@@ -149,8 +743,165 @@ pub fn wait_for_message(socket: &UdpSocket, who: &SocketAddr, msg: Message) {
     }
 }
 
-// Just sends two times so that the message is received
-pub fn send_safely(socket: &UdpSocket, msg: &Message, who: &SocketAddr) {
-    send_message(socket, msg, who);
-    send_message(socket, msg, who);
+// Wait for the game to start, returns the side and configuration sent by the server,
+// or None if the server stopped responding
+// Keeps sending heartbeats, so that the server knows we are still waiting
+pub fn wait_for_ready(
+    socket: &UdpSocket,
+    channel: &ReliableChannel,
+    who: &SocketAddr,
+    player_id: PlayerId,
+) -> Option<(Option<Side>, Configuration)> {
+    socket
+        .set_read_timeout(Some(HEARTBEAT_INTERVAL))
+        .expect("set_read_timeout call failed");
+    let mut last_heard = Instant::now();
+    let mut last_heartbeat = Instant::now();
+    loop {
+        if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+            send_message(socket, &Message::Heartbeat(player_id), who);
+            last_heartbeat = Instant::now();
+        }
+        if let ReadType::MessageRead(msg, addr) = get_message(socket, channel) {
+            if addr == *who {
+                last_heard = Instant::now();
+                if let Message::Ready(side, config) = msg {
+                    return Some((side, config));
+                }
+            }
+        }
+        if last_heard.elapsed() >= SERVER_TIMEOUT {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two channels talking over localhost
+    fn pair() -> ((UdpSocket, ReliableChannel), (UdpSocket, ReliableChannel)) {
+        let open = || {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            (socket, ReliableChannel::new())
+        };
+        (open(), open())
+    }
+
+    fn receive(socket: &UdpSocket, channel: &ReliableChannel) -> Option<Message> {
+        for _ in 0..10 {
+            if let ReadType::MessageRead(msg, _) = get_message(socket, channel) {
+                return Some(msg);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn far_ahead_messages_and_quiet_strangers_are_dropped() {
+        let ((a, a_channel), (b, _)) = pair();
+        let b_addr = b.local_addr().unwrap();
+
+        // 1 is missing, the rest waits for it
+        for seq in (0..1000).filter(|&seq| seq != 1) {
+            a_channel.received(&a, 7, seq, Message::Score(seq, 0), b_addr);
+        }
+        assert_eq!(
+            a_channel.next_delivered(),
+            Some((Message::Score(0, 0), b_addr))
+        );
+        assert_eq!(a_channel.next_delivered(), None);
+        {
+            let state = a_channel.state.lock().unwrap();
+            assert_eq!(state.peers[&b_addr].early.len(), EARLY_WINDOW as usize);
+        }
+
+        a_channel
+            .state
+            .lock()
+            .unwrap()
+            .peers
+            .get_mut(&b_addr)
+            .unwrap()
+            .last_active = Instant::now() - ANONYMOUS_PEER_TIMEOUT;
+        a_channel.resend(&a);
+        assert!(a_channel.state.lock().unwrap().peers.is_empty());
+
+        // its stream goes on where it was
+        a_channel.received(&a, 7, 1000, Message::Score(1000, 0), b_addr);
+        assert_eq!(
+            a_channel.next_delivered(),
+            Some((Message::Score(1000, 0), b_addr))
+        );
+    }
+
+    #[test]
+    fn stream_from_peer_goes_on_after_giving_up_on_it() {
+        let ((a, a_channel), (b, b_channel)) = pair();
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        b_channel.send(&b, &Message::Score(1, 0), &a_addr);
+        assert_eq!(receive(&a, &a_channel), Some(Message::Score(1, 0)));
+
+        // b never acknowledges this one, as if all its acks were lost
+        a_channel.send(&a, &Message::Score(9, 9), &b_addr);
+        {
+            let mut state = a_channel.state.lock().unwrap();
+            let outgoing = &mut state.peers.get_mut(&b_addr).unwrap().unacked[0];
+            outgoing.transmissions = MAX_TRANSMISSIONS;
+            outgoing.last_sent = Instant::now() - RETRANSMIT_INTERVAL;
+        }
+        a_channel.resend(&a);
+        assert!(!a_channel.pending(&b_addr));
+
+        b_channel.send(&b, &Message::Score(2, 0), &a_addr);
+        b_channel.send(&b, &Message::Score(3, 0), &a_addr);
+        assert_eq!(receive(&a, &a_channel), Some(Message::Score(2, 0)));
+        assert_eq!(receive(&a, &a_channel), Some(Message::Score(3, 0)));
+
+        // and what a sends now is delivered too, on a new stream
+        a_channel.send(&a, &Message::Score(0, 1), &b_addr);
+        // b still gets the given up one first, it was in flight
+        let mut next = receive(&b, &b_channel);
+        if next == Some(Message::Score(9, 9)) {
+            next = receive(&b, &b_channel);
+        }
+        assert_eq!(next, Some(Message::Score(0, 1)));
+    }
+
+    #[test]
+    fn replayed_packets_are_dropped() {
+        let ((a, a_channel), (b, _)) = pair();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        a_channel.authenticate(&b_addr, 3, 42);
+        let signed = |counter| {
+            let packet = Packet::Unreliable(Message::Heartbeat(3));
+            codec::encode(&Datagram {
+                packet,
+                signature: Some(auth::sign(&packet, 3, counter, 42)),
+            })
+        };
+        for counter in [5, 5, 4, 6] {
+            b.send_to(&signed(counter), a_addr).unwrap();
+        }
+        assert_eq!(receive(&a, &a_channel), Some(Message::Heartbeat(3)));
+        assert_eq!(receive(&a, &a_channel), Some(Message::Heartbeat(3)));
+        assert_eq!(receive(&a, &a_channel), None);
+        assert_eq!(a_channel.take_rejected(), 2);
+
+        // nor sent again from another address the player moved to
+        let (c, _) = pair().0;
+        a_channel.authenticate(&c.local_addr().unwrap(), 3, 42);
+        a_channel.close(&b_addr);
+        c.send_to(&signed(6), a_addr).unwrap();
+        c.send_to(&signed(7), a_addr).unwrap();
+        assert_eq!(receive(&a, &a_channel), Some(Message::Heartbeat(3)));
+        assert_eq!(receive(&a, &a_channel), None);
+        assert_eq!(a_channel.take_rejected(), 1);
+    }
 }
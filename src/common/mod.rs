@@ -1,4 +1,17 @@
 pub mod arguments;
+pub mod auth;
+pub mod codec;
+pub mod external_bot;
+pub mod gym;
+pub mod interpolation;
+pub mod matchmaking;
 pub mod messages;
 pub mod multiplayer_pong;
+#[cfg(feature = "graphics")]
 pub mod player_state;
+pub mod prediction;
+pub mod rating;
+pub mod replay;
+#[cfg(feature = "graphics")]
+pub mod replay_viewer;
+pub mod tournament;
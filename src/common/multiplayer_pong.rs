@@ -1,6 +1,8 @@
 use crate::ball::BallAbstract;
 use crate::configuration::FromConfiguration;
+use crate::deterministic::StateHasher;
 use crate::paddle::paddle_from_configuration;
+use crate::rules::{Match, Side};
 use crate::state::{game_frame, Input, RoundResult};
 use crate::{paddle::Paddle, paddle_like::RectangularPaddle};
 
@@ -10,13 +12,15 @@ pub struct MultiplayerPong {
     pub ball: BallAbstract,
     pub left_paddle: Paddle<RectangularPaddle>,
     pub right_paddle: Paddle<RectangularPaddle>,
+    pub game: Match,
 }
 
 impl FromConfiguration for MultiplayerPong {
     fn from_configuration(config: &crate::configuration::Configuration) -> Self {
         let ball = BallAbstract::from_configuration(config);
         let (left_paddle, right_paddle) = paddle_from_configuration(config);
-        Self::new(ball, left_paddle, right_paddle)
+        let game = Match::new(config.rules, ball.first_serve());
+        Self::new(ball, left_paddle, right_paddle, game)
     }
 }
 
@@ -25,11 +29,13 @@ impl MultiplayerPong {
         ball: BallAbstract,
         left_paddle: Paddle<RectangularPaddle>,
         right_paddle: Paddle<RectangularPaddle>,
+        game: Match,
     ) -> Self {
         Self {
             ball,
             left_paddle,
             right_paddle,
+            game,
         }
     }
 
@@ -48,6 +54,7 @@ impl MultiplayerPong {
             &mut self.right_paddle,
             dt,
             &input,
+            &mut self.game,
         );
         let game_state = GameState::new(
             tick,
@@ -57,6 +64,29 @@ impl MultiplayerPong {
         );
         (rr, game_state)
     }
+
+    // Hash of everything the next rounds depend on, in this order: ball position,
+    // ball velocity, then left and right paddle, each position and velocity,
+    // then the match: left and right score, side served towards (0 left, 1 right), time to serve.
+    // Two simulations with the same hash after a tick play on identically, so comparing
+    // hashes between runs or machines finds the tick where they diverged.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        hasher.write_vec2(self.ball.get_position());
+        hasher.write_vec2(self.ball.get_velocity());
+        for paddle in [&self.left_paddle, &self.right_paddle] {
+            hasher.write_vec2(paddle.get_position());
+            hasher.write_f32(paddle.get_velocity());
+        }
+        hasher.write_u32(self.game.score.0);
+        hasher.write_u32(self.game.score.1);
+        hasher.write_u32(match self.game.serve_towards {
+            Side::Left => 0,
+            Side::Right => 1,
+        });
+        hasher.write_f32(self.game.serve_in);
+        hasher.finish()
+    }
 }
 
 pub struct Player {
@@ -1,10 +1,17 @@
 use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
 
+use super::interpolation::SnapshotBuffer;
 use super::messages::{
-    get_message, send_message, send_safely, Direction, GameState, PlayerMove, ReadType, Side,
+    get_message, send_message, GameState, Pause, PlayerMove, ReadType, ReliableChannel,
+    SessionToken, Side, CONNECTION_LOST_AFTER, HEARTBEAT_INTERVAL, SERVER_TIMEOUT,
 };
+use super::prediction::PaddlePredictor;
 use crate::configuration::{Configuration, FromConfiguration};
-use crate::game::{ball::Ball, paddle::Paddle, paddle_like::PaddleLike};
+use crate::game::{
+    paddle::Paddle,
+    render::{Ball, PaddleDraw},
+};
 use crate::messages::{Message, PlayerId};
 use crate::paddle::paddle_from_configuration;
 use ggez::{graphics, Context, GameError, GameResult};
@@ -12,41 +19,205 @@ use ggez::{graphics, Context, GameError, GameResult};
 const DESIRED_FPS: u32 = 50;
 
 const SCREEN_COLOR: graphics::Color = graphics::Color::BLACK;
+const HIGHLIGHT_COLOR: graphics::Color = graphics::Color::WHITE;
+const HUD_COLOR: graphics::Color = graphics::Color::WHITE;
+const HUD_MARGIN: f32 = 10.0;
+// how long a closing player waits for the server to acknowledge it's leaving
+const QUIT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+// how the player wants the game shown
+pub struct ViewOptions {
+    pub interpolation_delay: Duration,
+    // right player sees the game flipped, so that both see themselves on the left
+    pub mirror: bool,
+}
+
+// who the player is on the server
+pub struct Session {
+    // None for spectators
+    pub side: Option<Side>,
+    pub player_id: PlayerId,
+    pub token: SessionToken,
+}
 
 struct Game {
     player_id: PlayerId,
+    // currently rendered positions
     game_state: GameState,
+    snapshots: SnapshotBuffer,
     left_score: u32,
     right_score: u32,
     _timer: f32,
 }
 
-pub struct PlayerState<L: PaddleLike, R: PaddleLike> {
+pub struct PlayerState<L: PaddleDraw, R: PaddleDraw> {
     paddle_left: Paddle<L>,
     paddle_right: Paddle<R>,
     ball: Ball,
     game: Game,
-    _side: Side,
-    tick: u32,
+    // None when only watching
+    side: Option<Side>,
+    // keys currently held
+    up: bool,
+    down: bool,
+    // own paddle is predicted, the rest is interpolated
+    predictor: PaddlePredictor,
+    // time not yet simulated by the predictor
+    prediction_time: f32,
+    mirrored: bool,
+    screen: graphics::Rect,
+    token: SessionToken,
+    // why the server stopped the game, if it did
+    pause: Option<Pause>,
+    // winner, once the match is decided
+    winner: Option<Side>,
+    last_heartbeat: Instant,
+    last_heard_from_server: Instant,
+    last_rejoin: Instant,
     socket: UdpSocket,
+    channel: ReliableChannel,
     dest_addr: SocketAddr,
 }
 
-impl<L: PaddleLike, R: PaddleLike> PlayerState<L, R> {
+impl<L: PaddleDraw, R: PaddleDraw> PlayerState<L, R> {
     pub fn get_player_id(&self) -> PlayerId {
         self.game.player_id
     }
+
+    // score line with own side marked, own score first if the view is mirrored
+    fn hud_text(&self) -> String {
+        let Some(own_side) = self.side else {
+            return format!(
+                "Left {} : {} Right (watching)",
+                self.game.left_score, self.game.right_score
+            );
+        };
+        let (you, opponent) = match own_side {
+            Side::Left => (self.game.left_score, self.game.right_score),
+            Side::Right => (self.game.right_score, self.game.left_score),
+        };
+        let side = match own_side {
+            Side::Left => "left",
+            Side::Right => "right",
+        };
+        if self.mirrored || own_side == Side::Left {
+            format!("You ({}) {} : {} Opponent", side, you, opponent)
+        } else {
+            format!("Opponent {} : {} You ({})", opponent, you, side)
+        }
+    }
+
+    // shown in the middle of the screen when the game doesn't go on
+    fn pause_text(&self) -> Option<String> {
+        if let Some(winner) = self.winner {
+            let (left, right) = (self.game.left_score, self.game.right_score);
+            let result = match (self.side, winner) {
+                (None, Side::Left) => "Left won",
+                (None, Side::Right) => "Right won",
+                (Some(side), winner) if side == winner => "You won",
+                (Some(_), _) => "You lost",
+            };
+            return Some(format!(
+                "{} {} : {}, close the window to leave",
+                result, left, right
+            ));
+        }
+        if self.last_heard_from_server.elapsed() >= CONNECTION_LOST_AFTER {
+            return Some("Connection lost, reconnecting...".to_string());
+        }
+        match self.pause? {
+            Pause::WaitingFor(player_id, seconds) if self.side.is_none() => Some(format!(
+                "Player {} lost connection, waiting {} s for it",
+                player_id, seconds
+            )),
+            Pause::WaitingFor(_, seconds) => Some(format!(
+                "Opponent lost connection, waiting {} s for it",
+                seconds
+            )),
+            Pause::Resuming(seconds) => Some(format!("Resuming in {}", seconds)),
+        }
+    }
+
+    // Server might not hear us at the old address anymore (e.g. network changed),
+    // so a fresh socket is tried. Server answers to it if it knows the session.
+    fn rejoin(&mut self) {
+        let ip = self
+            .socket
+            .local_addr()
+            .expect("Couldn't get local address")
+            .ip();
+        match UdpSocket::bind((ip, 0)) {
+            Ok(socket) => {
+                socket
+                    .set_nonblocking(true)
+                    .expect("set_nonblocking call failed");
+                self.socket = socket;
+            }
+            Err(e) => eprintln!("Couldn't bind a new socket: {}", e),
+        }
+        // server sees a new peer, both sides start numbering reliable messages anew
+        self.channel.forget(&self.dest_addr);
+        send_message(&self.socket, &Message::Rejoin(self.token), &self.dest_addr);
+        self.last_rejoin = Instant::now();
+    }
+
+    fn reconcile(&mut self, gs: GameState) {
+        match self.side {
+            None => {}
+            Some(Side::Left) => self.predictor.reconcile(
+                &mut self.paddle_left,
+                gs.tick,
+                gs.left_paddle,
+                gs.left_input_tick,
+            ),
+            Some(Side::Right) => self.predictor.reconcile(
+                &mut self.paddle_right,
+                gs.tick,
+                gs.right_paddle,
+                gs.right_input_tick,
+            ),
+        }
+    }
+
+    // moves own paddle right away and tells the server about the input
+    fn predict(&mut self, own_side: Side, elapsed: f32) {
+        self.prediction_time += elapsed;
+        while self.prediction_time >= self.predictor.step_duration() {
+            self.prediction_time -= self.predictor.step_duration();
+            let tick = match own_side {
+                Side::Left => self
+                    .predictor
+                    .step(&mut self.paddle_left, self.up, self.down),
+                Side::Right => self
+                    .predictor
+                    .step(&mut self.paddle_right, self.up, self.down),
+            };
+            let move_msg = Message::Move(PlayerMove {
+                player_id: self.get_player_id(),
+                tick,
+                up: self.up,
+                down: self.down,
+            });
+            send_message(&self.socket, &move_msg, &self.dest_addr);
+        }
+    }
 }
 
-impl<L: PaddleLike + FromConfiguration, R: PaddleLike + FromConfiguration> PlayerState<L, R> {
+impl<L: PaddleDraw + FromConfiguration, R: PaddleDraw + FromConfiguration> PlayerState<L, R> {
     pub fn new(
         config: Configuration,
         ctx: &mut Context,
-        side: Side,
+        session: Session,
         socket: UdpSocket,
+        channel: ReliableChannel,
         dest_addr: SocketAddr,
-        player_id: PlayerId,
+        view: ViewOptions,
     ) -> Self {
+        let Session {
+            side,
+            player_id,
+            token,
+        } = session;
         let (paddle_left, paddle_right) = paddle_from_configuration(&config);
         Self {
             paddle_left,
@@ -55,43 +226,83 @@ impl<L: PaddleLike + FromConfiguration, R: PaddleLike + FromConfiguration> Playe
             game: Game {
                 player_id,
                 game_state: GameState::from_configuration(&config),
+                snapshots: SnapshotBuffer::new(config.ticks_per_second, view.interpolation_delay),
                 left_score: 0,
                 right_score: 0,
                 _timer: 0.0,
             },
-            _side: side,
-            tick: 0,
+            side,
+            up: false,
+            down: false,
+            predictor: PaddlePredictor::new(config.ticks_per_second),
+            prediction_time: 0.0,
+            mirrored: view.mirror && side == Some(Side::Right),
+            screen: graphics::Rect::new(0.0, 0.0, config.screen_width, config.screen_height),
+            token,
+            pause: None,
+            winner: None,
+            last_heartbeat: Instant::now(),
+            last_heard_from_server: Instant::now(),
+            last_rejoin: Instant::now(),
             socket,
+            channel,
             dest_addr,
         }
     }
 }
 
-impl<L: PaddleLike, R: PaddleLike> ggez::event::EventHandler<GameError> for PlayerState<L, R> {
+impl<L: PaddleDraw, R: PaddleDraw> ggez::event::EventHandler<GameError> for PlayerState<L, R> {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
         // let dt :f32 = ctx.time.delta();
-        let _dt = 1.0 / DESIRED_FPS as f32;
+        let dt = 1.0 / DESIRED_FPS as f32;
         // let mut num_of_updates = 0;
         while ctx.time.check_update_time(DESIRED_FPS) {
             loop {
-                match get_message(&self.socket) {
+                match get_message(&self.socket, &self.channel) {
                     ReadType::AllRead => break,
-                    ReadType::WrongRead => continue,
+                    ReadType::WrongRead | ReadType::VersionMismatch(..) => continue,
                     ReadType::MessageRead(msg, addr) => {
                         if addr == self.dest_addr {
+                            self.last_heard_from_server = Instant::now();
                             match msg {
                                 Message::State(gs) => {
-                                    self.game.game_state.update(gs);
+                                    self.pause = None;
+                                    self.game.snapshots.insert(gs, Instant::now());
+                                    self.reconcile(gs);
                                 }
                                 Message::Score(left, right) => {
                                     self.game.left_score = left;
                                     self.game.right_score = right;
                                     println!("Scoree! left: {}, right: {}", left, right);
                                 }
-                                Message::EndingGame(_) => {
-                                    println!("Ending game");
+                                Message::MatchOver { winner, score } => {
+                                    (self.game.left_score, self.game.right_score) = score;
+                                    if self.winner.is_none() {
+                                        println!(
+                                            "Match over, {:?} won {}:{}",
+                                            winner, score.0, score.1
+                                        );
+                                    }
+                                    self.winner = Some(winner);
+                                }
+                                Message::EndingGame(player_id) => {
+                                    if player_id == self.get_player_id() {
+                                        println!("Ending game");
+                                    } else if self.side.is_none() {
+                                        println!("Player {} left the game", player_id);
+                                    } else {
+                                        println!("Opponent left the game");
+                                    }
                                     std::process::exit(0);
                                 }
+                                Message::Paused(pause) => {
+                                    self.pause = Some(pause);
+                                    // server time stood still, so the clock estimate is off
+                                    self.game.snapshots.resync();
+                                }
+                                Message::Heartbeat(_) => {}
+                                // answers to Rejoin, we are already set up
+                                Message::Ok(..) | Message::Ready(..) => {}
                                 _ => {
                                     eprintln!("Unexpected message: {:?}", msg);
                                 }
@@ -101,10 +312,43 @@ impl<L: PaddleLike, R: PaddleLike> ggez::event::EventHandler<GameError> for Play
                 }
             }
 
-            self.paddle_left
-                .set_position(self.game.game_state.left_paddle);
-            self.paddle_right
-                .set_position(self.game.game_state.right_paddle);
+            self.channel.resend(&self.socket);
+
+            if self.last_heard_from_server.elapsed() >= SERVER_TIMEOUT {
+                eprintln!("Server stopped responding");
+                std::process::exit(1);
+            }
+            if self.last_heard_from_server.elapsed() >= CONNECTION_LOST_AFTER
+                && self.last_rejoin.elapsed() >= HEARTBEAT_INTERVAL
+            {
+                self.rejoin();
+            }
+            if self.last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                send_message(
+                    &self.socket,
+                    &Message::Heartbeat(self.get_player_id()),
+                    &self.dest_addr,
+                );
+                self.last_heartbeat = Instant::now();
+            }
+
+            // inputs would be lost anyway while the server doesn't simulate
+            if let (Some(own_side), None, None) = (self.side, self.pause, self.winner) {
+                self.predict(own_side, dt);
+            }
+
+            if let Some(gs) = self.game.snapshots.sample(Instant::now()) {
+                self.game.game_state = gs;
+            }
+            // own paddle is predicted, the others follow the server
+            if self.side != Some(Side::Left) {
+                self.paddle_left
+                    .set_position(self.game.game_state.left_paddle);
+            }
+            if self.side != Some(Side::Right) {
+                self.paddle_right
+                    .set_position(self.game.game_state.right_paddle);
+            }
             self.ball.set_position(self.game.game_state.ball);
 
             // num_of_updates += 1;
@@ -116,20 +360,68 @@ impl<L: PaddleLike, R: PaddleLike> ggez::event::EventHandler<GameError> for Play
     }
 
     fn quit_event(&mut self, _ctx: &mut Context) -> Result<bool, GameError> {
-        send_safely(
+        self.channel.send(
             &self.socket,
             &Message::EndingGame(self.get_player_id()),
             &self.dest_addr,
         );
+        self.channel
+            .flush(&self.socket, &self.dest_addr, QUIT_FLUSH_TIMEOUT);
         Ok(true)
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         let mut canvas = graphics::Canvas::from_frame(ctx, SCREEN_COLOR);
 
+        if self.mirrored {
+            let mut flipped = self.screen;
+            flipped.x = self.screen.w;
+            flipped.w = -self.screen.w;
+            canvas.set_screen_coordinates(flipped);
+        } else {
+            canvas.set_screen_coordinates(self.screen);
+        }
         self.ball.draw(&mut canvas);
-        self.paddle_left.draw(&mut canvas);
-        self.paddle_right.draw(&mut canvas);
+        match self.side {
+            Some(Side::Left) => {
+                self.paddle_left
+                    .draw_highlighted(&mut canvas, HIGHLIGHT_COLOR);
+                self.paddle_right.draw(&mut canvas);
+            }
+            Some(Side::Right) => {
+                self.paddle_left.draw(&mut canvas);
+                self.paddle_right
+                    .draw_highlighted(&mut canvas, HIGHLIGHT_COLOR);
+            }
+            None => {
+                self.paddle_left.draw(&mut canvas);
+                self.paddle_right.draw(&mut canvas);
+            }
+        }
+
+        // text is never mirrored
+        canvas.set_screen_coordinates(self.screen);
+        let hud = graphics::Text::new(self.hud_text());
+        let hud_width = hud.measure(ctx)?.x;
+        canvas.draw(
+            &hud,
+            graphics::DrawParam::new()
+                .dest(glam::vec2((self.screen.w - hud_width) / 2.0, HUD_MARGIN))
+                .color(HUD_COLOR),
+        );
+        if let Some(text) = self.pause_text() {
+            let text = graphics::Text::new(text);
+            let size = text.measure(ctx)?;
+            canvas.draw(
+                &text,
+                graphics::DrawParam::new()
+                    .dest(glam::vec2(
+                        (self.screen.w - size.x) / 2.0,
+                        (self.screen.h - size.y) / 2.0,
+                    ))
+                    .color(HUD_COLOR),
+            );
+        }
 
         canvas.finish(ctx)?;
         ggez::timer::yield_now();
@@ -143,18 +435,14 @@ impl<L: PaddleLike, R: PaddleLike> ggez::event::EventHandler<GameError> for Play
         _repeat: bool,
     ) -> GameResult {
         // println!("key pressed: {:?}", keyinput.keycode);
-        if let Some(dir) = match keyinput.keycode {
-            Some(ggez::input::keyboard::KeyCode::Up) => Some(Direction::Up(true)),
-            Some(ggez::input::keyboard::KeyCode::Down) => Some(Direction::Down(true)),
-            _ => None,
-        } {
-            let move_msg = Message::Move(PlayerMove {
-                player_id: self.get_player_id(),
-                tick: self.tick,
-                dir,
-            });
-            self.tick += 1;
-            send_message(&self.socket, &move_msg, &self.dest_addr);
+        match keyinput.keycode {
+            Some(ggez::input::keyboard::KeyCode::Up | ggez::input::keyboard::KeyCode::W) => {
+                self.up = true
+            }
+            Some(ggez::input::keyboard::KeyCode::Down | ggez::input::keyboard::KeyCode::S) => {
+                self.down = true
+            }
+            _ => (),
         }
         Ok(())
     }
@@ -165,22 +453,14 @@ impl<L: PaddleLike, R: PaddleLike> ggez::event::EventHandler<GameError> for Play
         keyinput: ggez::input::keyboard::KeyInput,
     ) -> GameResult {
         // println!("key released: {:?}", keyinput.keycode);
-        if let Some(dir) = match keyinput.keycode {
-            Some(ggez::input::keyboard::KeyCode::Up) => Some(Direction::Up(false)),
-            Some(ggez::input::keyboard::KeyCode::Down) => Some(Direction::Down(false)),
-            _ => None,
-        } {
-            let move_msg = Message::Move(PlayerMove {
-                player_id: self.get_player_id(),
-                tick: self.tick,
-                dir,
-            });
-            // sending two times, because it is crucial that server receives the message
-            // 'key down event' is fired multiple times so it is not nedded there
-            self.tick += 1;
-            send_message(&self.socket, &move_msg, &self.dest_addr);
-            self.tick += 1;
-            send_message(&self.socket, &move_msg, &self.dest_addr);
+        match keyinput.keycode {
+            Some(ggez::input::keyboard::KeyCode::Up | ggez::input::keyboard::KeyCode::W) => {
+                self.up = false
+            }
+            Some(ggez::input::keyboard::KeyCode::Down | ggez::input::keyboard::KeyCode::S) => {
+                self.down = false
+            }
+            _ => (),
         }
         Ok(())
     }
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+use super::messages::Tick;
+use crate::paddle::Paddle;
+use crate::paddle_like::PaddleLike;
+
+// inputs older than that are dropped even if the server never acknowledged them
+const MAX_UNACKNOWLEDGED: usize = 256;
+
+// Client side prediction of the player's own paddle.
+// Every input is applied locally right away, and remembered until the server
+// acknowledges it. When an authoritative state arrives, the paddle is put where
+// the server has it and the inputs the server hasn't processed yet are replayed on top.
+pub struct PaddlePredictor {
+    // (input tick, up, down)
+    unacknowledged: VecDeque<(Tick, bool, bool)>,
+    next_tick: Tick,
+    // newest server tick already reconciled with
+    server_tick: Option<Tick>,
+    dt: f32,
+}
+
+impl PaddlePredictor {
+    pub fn new(ticks_per_second: u32) -> Self {
+        Self {
+            unacknowledged: VecDeque::new(),
+            next_tick: 1,
+            server_tick: None,
+            dt: 1.0 / ticks_per_second as f32,
+        }
+    }
+
+    // seconds of a single step, the same as the server's
+    pub fn step_duration(&self) -> f32 {
+        self.dt
+    }
+
+    // Applies one step of input to the paddle, returns tick of the input to send to the server
+    pub fn step<E: PaddleLike>(&mut self, paddle: &mut Paddle<E>, up: bool, down: bool) -> Tick {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        paddle.update(self.dt, up, down);
+        self.unacknowledged.push_back((tick, up, down));
+        if self.unacknowledged.len() > MAX_UNACKNOWLEDGED {
+            self.unacknowledged.pop_front();
+        }
+        tick
+    }
+
+    // `server_position` is where the server had the paddle at `server_tick`,
+    // after applying inputs up to `acknowledged`
+    pub fn reconcile<E: PaddleLike>(
+        &mut self,
+        paddle: &mut Paddle<E>,
+        server_tick: Tick,
+        server_position: (f32, f32),
+        acknowledged: Tick,
+    ) {
+        if self.server_tick.is_some_and(|t| t >= server_tick) {
+            return;
+        }
+        self.server_tick = Some(server_tick);
+        while self
+            .unacknowledged
+            .front()
+            .is_some_and(|&(tick, _, _)| tick <= acknowledged)
+        {
+            self.unacknowledged.pop_front();
+        }
+        paddle.set_position(server_position);
+        for &(_, up, down) in &self.unacknowledged {
+            paddle.update(self.dt, up, down);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Configuration;
+    use crate::paddle::paddle_from_configuration;
+    use crate::paddle_like::RectangularPaddle;
+
+    fn paddle() -> Paddle<RectangularPaddle> {
+        let config = Configuration::default();
+        paddle_from_configuration::<RectangularPaddle, RectangularPaddle>(&config).0
+    }
+
+    // what the player pressed at that tick
+    fn input(tick: Tick) -> (bool, bool) {
+        match tick % 40 {
+            0..=24 => (true, false),
+            25..=29 => (false, false),
+            30..=38 => (false, true),
+            _ => (true, true),
+        }
+    }
+
+    // where the paddle is after `steps` of input on top of `position`
+    fn stepped(position: glam::Vec2, dt: f32, steps: impl IntoIterator<Item = Tick>) -> glam::Vec2 {
+        let mut paddle = paddle();
+        paddle.set_position(position);
+        for tick in steps {
+            let (up, down) = input(tick);
+            paddle.update(dt, up, down);
+        }
+        paddle.get_position()
+    }
+
+    #[test]
+    fn pending_inputs_are_replayed_on_the_server_position() {
+        let ticks_per_second = Configuration::default().ticks_per_second;
+        for (acknowledged, pending) in [(1, 1), (10, 3), (30, 12), (50, 0)] {
+            let mut predictor = PaddlePredictor::new(ticks_per_second);
+            let dt = predictor.step_duration();
+            let mut client = paddle();
+            for tick in 1..=acknowledged + pending {
+                let (up, down) = input(tick);
+                assert_eq!(predictor.step(&mut client, up, down), tick);
+            }
+            // server lost an input and has the paddle elsewhere than the client thought
+            let start = paddle().get_position();
+            let server = stepped(start, dt, (1..=acknowledged).filter(|&tick| tick != 1));
+            predictor.reconcile(&mut client, acknowledged, server.into(), acknowledged);
+            assert_eq!(
+                client.get_position(),
+                stepped(server, dt, acknowledged + 1..=acknowledged + pending),
+                "{} pending after {}",
+                pending,
+                acknowledged
+            );
+        }
+    }
+
+    #[test]
+    fn older_server_states_are_ignored() {
+        let mut predictor = PaddlePredictor::new(30);
+        let dt = predictor.step_duration();
+        let mut client = paddle();
+        for tick in 1..=8 {
+            let (up, down) = input(tick);
+            predictor.step(&mut client, up, down);
+        }
+        let server = glam::vec2(5.0, 250.0);
+        predictor.reconcile(&mut client, 6, server.into(), 5);
+        let reconciled = client.get_position();
+        assert_eq!(reconciled, stepped(server, dt, 6..=8));
+        // a state from before the one already used, or the same one again, changes nothing
+        predictor.reconcile(&mut client, 4, (5.0, 400.0), 3);
+        predictor.reconcile(&mut client, 6, (5.0, 400.0), 5);
+        assert_eq!(client.get_position(), reconciled);
+        // acknowledged inputs aren't replayed again by the next state
+        let server = glam::vec2(5.0, 260.0);
+        predictor.reconcile(&mut client, 7, server.into(), 7);
+        assert_eq!(client.get_position(), stepped(server, dt, 8..=8));
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use super::auth::PasswordHash;
+
+// Elo ratings, see https://en.wikipedia.org/wiki/Elo_rating_system
+
+// rating of a player who hasn't played yet
+pub const DEFAULT_RATING: f64 = 1500.0;
+// how much a single game can move a rating
+const K_FACTOR: f64 = 32.0;
+
+// chance of winning against the opponent, as the rating difference says
+pub fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+// `score` is what the first player got out of the game: 1 win, 0.5 draw, 0 loss
+pub fn updated_ratings(first: f64, second: f64, score: f64) -> (f64, f64) {
+    let change = K_FACTOR * (score - expected_score(first, second));
+    (first + change, second - change)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct PlayerRating {
+    pub rating: f64,
+    pub games: u32,
+}
+
+impl Default for PlayerRating {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            games: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RatingsError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for RatingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RatingsError::Io(e) => write!(f, "couldn't access ratings file: {}", e),
+            RatingsError::Parse(e) => write!(f, "couldn't parse ratings file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RatingsError {}
+
+// Ratings of named players, kept in a json file between server runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ratings {
+    players: BTreeMap<String, PlayerRating>,
+    // name -> hex of sha256 of the hashed secret it was claimed with,
+    // only who knows the secret plays under the name
+    #[serde(default)]
+    owners: BTreeMap<String, String>,
+}
+
+// what the file keeps of a secret, so that the file itself doesn't let anyone in
+fn secret_fingerprint(secret: &PasswordHash) -> String {
+    Sha256::digest(secret)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl Ratings {
+    // missing file is the same as an empty one
+    pub fn load(path: &Path) -> Result<Self, RatingsError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| RatingsError::Parse(e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(RatingsError::Io(e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RatingsError> {
+        let text = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, text).map_err(RatingsError::Io)
+    }
+
+    pub fn get(&self, name: &str) -> PlayerRating {
+        self.players.get(name).copied().unwrap_or_default()
+    }
+
+    // Whether the name may be played under with this secret,
+    // a name nobody has played under yet becomes the secret's.
+    pub fn claim(&mut self, name: &str, secret: &PasswordHash) -> bool {
+        let fingerprint = secret_fingerprint(secret);
+        match self.owners.get(name) {
+            Some(owner) => *owner == fingerprint,
+            None => {
+                self.owners.insert(name.to_string(), fingerprint);
+                true
+            }
+        }
+    }
+
+    // Updates both players after a decided game, nothing to update if they're the same one.
+    // Only who won counts, not by how much: 11:0 moves ratings as much as 12:10, so that
+    // a player who is sure to win gains nothing by running up the score.
+    pub fn record(&mut self, first: &str, second: &str, first_won: bool) {
+        if first == second {
+            return;
+        }
+        let (a, b) = (self.get(first), self.get(second));
+        let score = if first_won { 1.0 } else { 0.0 };
+        let (rating_a, rating_b) = updated_ratings(a.rating, b.rating, score);
+        self.players.insert(
+            first.to_string(),
+            PlayerRating {
+                rating: rating_a,
+                games: a.games + 1,
+            },
+        );
+        self.players.insert(
+            second.to_string(),
+            PlayerRating {
+                rating: rating_b,
+                games: b.games + 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::hash_password;
+
+    #[test]
+    fn name_belongs_to_the_first_secret() {
+        let mut ratings = Ratings::default();
+        assert!(ratings.claim("ann", &hash_password("one")));
+        assert!(ratings.claim("ann", &hash_password("one")));
+        assert!(!ratings.claim("ann", &hash_password("two")));
+        assert!(ratings.claim("bob", &hash_password("two")));
+    }
+
+    #[test]
+    fn game_against_oneself_changes_nothing() {
+        let mut ratings = Ratings::default();
+        ratings.record("ann", "ann", true);
+        assert_eq!(ratings.get("ann"), PlayerRating::default());
+
+        ratings.record("ann", "bob", true);
+        assert!(ratings.get("ann").rating > DEFAULT_RATING);
+        assert!(ratings.get("bob").rating < DEFAULT_RATING);
+        assert_eq!(ratings.get("bob").games, 1);
+    }
+}
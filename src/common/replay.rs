@@ -0,0 +1,370 @@
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::messages::{GameId, GameState, PlayerId, PlayerName, Tick};
+use super::multiplayer_pong::MultiplayerPong;
+use crate::configuration::{Configuration, FromConfiguration};
+use crate::state::RoundResult;
+
+// Replay file format
+//
+//   bytes 0..4  REPLAY_MAGIC
+//   byte  4     REPLAY_VERSION
+// then values encoded one after another with bincode (DefaultOptions, as in the binary codec):
+//   ReplayHeader
+//   Record, Record, ... until the end of the file
+//
+// There is a Record::Tick for every tick the server simulated, in order, the n-th one
+// (counting from 0) holds the keys applied in tick n. Ticks aren't simulated while
+// the game is paused, so pauses leave no trace.
+// After every CHECKSUM_INTERVAL-th tick comes Record::Checksum with MultiplayerPong::state_hash
+// after that tick (positions and velocities of everything, bit for bit).
+// Record::End with the final score closes a game which ended properly, that is when the match
+// was decided, or when a player left before that. A file cut short
+// (e.g. the server crashed) is still readable up to the last whole record.
+//
+// Re-simulating: MultiplayerPong::from_configuration(&header.config), then for the n-th tick
+// multi_game_round(left, right, 1.0 / ticks_per_second as f32, n), after it state_hash must
+// equal the recorded checksum. With physics = "deterministic" in the configuration that holds
+// on every platform, with "native" only where the maths library gives the same sin and cos.
+//
+// Bump REPLAY_VERSION whenever anything above, or any type stored in the file, changes.
+
+pub const REPLAY_MAGIC: [u8; 4] = *b"PGRP";
+pub const REPLAY_VERSION: u8 = 3;
+// ticks between checksums
+pub const CHECKSUM_INTERVAL: Tick = 30;
+
+// keys held by a player, as (up, down)
+pub type Keys = (bool, bool);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ReplayPlayer {
+    pub player_id: PlayerId,
+    pub name: Option<PlayerName>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ReplayHeader {
+    pub game_id: GameId,
+    // seconds since the unix epoch
+    pub started: u64,
+    pub config: Configuration,
+    pub left: ReplayPlayer,
+    pub right: ReplayPlayer,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Record {
+    Tick { left: Keys, right: Keys },
+    Checksum { tick: Tick, checksum: u64 },
+    End { score: (u32, u32) },
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+fn is_end_of_file(e: &bincode::Error) -> bool {
+    matches!(&**e, bincode::ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    NotAReplay,
+    // file written in another format version
+    Version(u8),
+    Malformed(String),
+    // re-simulation doesn't match the recorded checksum at this tick
+    Desync(Tick),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "couldn't read replay: {}", e),
+            ReplayError::NotAReplay => write!(f, "not a replay file"),
+            ReplayError::Version(version) => write!(
+                f,
+                "replay has format version {}, this program reads version {}",
+                version, REPLAY_VERSION
+            ),
+            ReplayError::Malformed(e) => write!(f, "malformed replay: {}", e),
+            ReplayError::Desync(tick) => {
+                write!(
+                    f,
+                    "re-simulation differs from the recording at tick {}",
+                    tick
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+// Writes the replay of a game while it's played.
+pub struct ReplayWriter {
+    out: BufWriter<File>,
+    // ticks recorded so far
+    ticks: Tick,
+}
+
+impl ReplayWriter {
+    pub fn create(path: &Path, header: &ReplayHeader) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&REPLAY_MAGIC)?;
+        out.write_all(&[REPLAY_VERSION])?;
+        let mut writer = Self { out, ticks: 0 };
+        writer.write(header)?;
+        Ok(writer)
+    }
+
+    fn write<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+        bincode_options()
+            .serialize_into(&mut self.out, value)
+            .map_err(io::Error::other)
+    }
+
+    // keys applied in the tick just simulated and the game after it
+    pub fn record_tick(
+        &mut self,
+        left: Keys,
+        right: Keys,
+        tick: Tick,
+        pong: &MultiplayerPong,
+    ) -> io::Result<()> {
+        self.write(&Record::Tick { left, right })?;
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(CHECKSUM_INTERVAL) {
+            self.write(&Record::Checksum {
+                tick,
+                checksum: pong.state_hash(),
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, score: (u32, u32)) -> io::Result<()> {
+        self.write(&Record::End { score })?;
+        self.out.flush()
+    }
+}
+
+// Replay read whole from a file.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub records: Vec<Record>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let mut input = BufReader::new(File::open(path).map_err(ReplayError::Io)?);
+        let mut start = [0; 5];
+        input.read_exact(&mut start).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => ReplayError::NotAReplay,
+            _ => ReplayError::Io(e),
+        })?;
+        if start[..4] != REPLAY_MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        if start[4] != REPLAY_VERSION {
+            return Err(ReplayError::Version(start[4]));
+        }
+        let header = bincode_options()
+            .deserialize_from(&mut input)
+            .map_err(|e| ReplayError::Malformed(e.to_string()))?;
+        let mut records = Vec::new();
+        loop {
+            match bincode_options().deserialize_from(&mut input) {
+                Ok(record) => records.push(record),
+                // end of the file, or of what got written of it
+                Err(e) if is_end_of_file(&e) => break,
+                Err(e) => return Err(ReplayError::Malformed(e.to_string())),
+            }
+        }
+        Ok(Self { header, records })
+    }
+
+    // keys of both players, tick by tick
+    pub fn inputs(&self) -> Vec<(Keys, Keys)> {
+        self.records
+            .iter()
+            .filter_map(|record| match *record {
+                Record::Tick { left, right } => Some((left, right)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Plays the whole match again.
+    pub fn simulate(&self) -> Timeline {
+        let config = &self.header.config;
+        let dt = 1.0 / config.ticks_per_second as f32;
+        let mut pong = MultiplayerPong::from_configuration(config);
+        let mut timeline = Timeline {
+            states: Vec::new(),
+            scores: Vec::new(),
+            hashes: Vec::new(),
+            goals: Vec::new(),
+        };
+        for (tick, (left, right)) in self.inputs().into_iter().enumerate() {
+            let tick = tick as Tick;
+            let (round_result, state) = pong.multi_game_round(left, right, dt, tick);
+            if !matches!(round_result, RoundResult::None) {
+                timeline.goals.push(tick);
+            }
+            timeline.states.push(state);
+            timeline.scores.push(pong.game.score);
+            timeline.hashes.push(pong.state_hash());
+        }
+        timeline
+    }
+
+    // Plays the match again, checking it against the recorded checksums,
+    // returns the score it ends with.
+    pub fn verify(&self) -> Result<(u32, u32), ReplayError> {
+        self.check(&self.simulate())
+    }
+
+    // verify with the match already played again by simulate
+    pub fn check(&self, timeline: &Timeline) -> Result<(u32, u32), ReplayError> {
+        let score = timeline.scores.last().copied().unwrap_or((0, 0));
+        for record in &self.records {
+            match *record {
+                Record::Tick { .. } => {}
+                Record::Checksum { tick, checksum } => {
+                    if timeline.hashes.get(tick as usize) != Some(&checksum) {
+                        return Err(ReplayError::Desync(tick));
+                    }
+                }
+                Record::End { score: recorded } => {
+                    if recorded != score {
+                        return Err(ReplayError::Desync(timeline.states.len() as Tick));
+                    }
+                }
+            }
+        }
+        Ok(score)
+    }
+}
+
+// The whole match, as re-simulated from a replay.
+pub struct Timeline {
+    // state after each tick
+    pub states: Vec<GameState>,
+    // score after each tick
+    pub scores: Vec<(u32, u32)>,
+    // MultiplayerPong::state_hash after each tick
+    pub hashes: Vec<u64>,
+    // ticks in which somebody scored, in order
+    pub goals: Vec<Tick>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKS: Tick = 3000;
+
+    // replay of a match in which the left paddle moves up and down and the right one stays,
+    // written to a file of the test's own, with the score it ended with
+    fn record(name: &str) -> (std::path::PathBuf, (u32, u32)) {
+        let path = std::env::temp_dir().join(format!("{}-{}.replay", name, std::process::id()));
+        let config = Configuration::default();
+        let player = |player_id| ReplayPlayer {
+            player_id,
+            name: None,
+        };
+        let header = ReplayHeader {
+            game_id: 1,
+            started: 0,
+            config,
+            left: player(0),
+            right: player(1),
+        };
+        let mut writer = ReplayWriter::create(&path, &header).unwrap();
+        let mut pong = MultiplayerPong::from_configuration(&config);
+        let dt = 1.0 / config.ticks_per_second as f32;
+        for tick in 0..TICKS {
+            let left = if tick % 200 < 100 {
+                (true, false)
+            } else {
+                (false, true)
+            };
+            let right = (false, false);
+            pong.multi_game_round(left, right, dt, tick);
+            writer.record_tick(left, right, tick, &pong).unwrap();
+        }
+        let score = pong.game.score;
+        writer.finish(score).unwrap();
+        (path, score)
+    }
+
+    #[test]
+    fn recorded_match_plays_again_the_same() {
+        let (path, score) = record("same");
+        let replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_ne!(score, (0, 0), "something to check");
+        assert_eq!(replay.inputs().len(), TICKS as usize);
+        assert_eq!(replay.verify().unwrap(), score);
+
+        let mut changed = replay.clone();
+        let first_tick = changed
+            .records
+            .iter_mut()
+            .find(|record| matches!(record, Record::Tick { .. }))
+            .unwrap();
+        *first_tick = Record::Tick {
+            left: (false, true),
+            right: (false, false),
+        };
+        assert!(matches!(changed.verify(), Err(ReplayError::Desync(_))));
+    }
+
+    #[test]
+    fn cut_file_is_read_up_to_the_last_whole_record() {
+        let (path, _) = record("cut");
+        let bytes = std::fs::read(&path).unwrap();
+        let full = Replay::load(&path).unwrap();
+        // where every record ends
+        let header_len = REPLAY_MAGIC.len() as u64
+            + 1
+            + bincode_options().serialized_size(&full.header).unwrap();
+        let mut ends = vec![header_len];
+        for record in &full.records {
+            ends.push(ends.last().unwrap() + bincode_options().serialized_size(record).unwrap());
+        }
+        assert_eq!(*ends.last().unwrap(), bytes.len() as u64);
+
+        // cut in the middle of records, from the first to the End
+        for whole in [
+            0,
+            1,
+            29,
+            30,
+            31,
+            full.records.len() / 2,
+            full.records.len() - 1,
+        ] {
+            let cut = (ends[whole] + ends[whole + 1]) / 2;
+            assert!(cut > ends[whole], "record {} is longer than a byte", whole);
+            std::fs::write(&path, &bytes[..cut as usize]).unwrap();
+            let replay = Replay::load(&path).unwrap();
+            assert_eq!(
+                replay.records,
+                full.records[..whole],
+                "cut in record {}",
+                whole
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use ggez::input::keyboard::{KeyCode, KeyInput};
+use ggez::input::mouse::MouseButton;
+use ggez::{graphics, Context, GameError, GameResult};
+
+use super::interpolation::blend_states;
+use super::messages::{GameState, PlayerName, Tick};
+use super::replay::{Replay, ReplayHeader, Timeline};
+use crate::configuration::FromConfiguration;
+use crate::game::{
+    paddle::Paddle,
+    render::{Ball, PaddleDraw},
+};
+use crate::paddle::paddle_from_configuration;
+
+const DESIRED_FPS: u32 = 60;
+
+// playback speeds, switched with up and down arrows
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+// how far page up and page down jump
+const JUMP_SECONDS: f32 = 5.0;
+
+const SCREEN_COLOR: graphics::Color = graphics::Color::BLACK;
+const HUD_COLOR: graphics::Color = graphics::Color::WHITE;
+const HUD_MARGIN: f32 = 10.0;
+const BAR_HEIGHT: f32 = 6.0;
+const BAR_COLOR: graphics::Color = graphics::Color::new(1.0, 1.0, 1.0, 0.3);
+const GOAL_MARK_COLOR: graphics::Color = graphics::Color::YELLOW;
+const HELP: [&str; 2] = [
+    "space: play/pause  up/down: speed  left/right: step  page up/down: 5 s",
+    "n/p: next/previous goal  home/end  digits + enter: go to tick  click the bar: seek",
+];
+
+pub struct ReplayViewer<L: PaddleDraw, R: PaddleDraw> {
+    paddle_left: Paddle<L>,
+    paddle_right: Paddle<R>,
+    ball: Ball,
+    header: ReplayHeader,
+    timeline: Timeline,
+    // tick shown, between two ticks the states are blended
+    position: f32,
+    playing: bool,
+    // index into SPEEDS
+    speed: usize,
+    // tick number typed so far, enter goes there
+    typed_tick: String,
+    screen: graphics::Rect,
+}
+
+impl<L: PaddleDraw + FromConfiguration, R: PaddleDraw + FromConfiguration> ReplayViewer<L, R> {
+    // `timeline` is Replay::simulate of the replay
+    pub fn new(replay: &Replay, timeline: Timeline, ctx: &mut Context, start_tick: Tick) -> Self {
+        let config = &replay.header.config;
+        let (paddle_left, paddle_right) = paddle_from_configuration(config);
+        let mut viewer = Self {
+            paddle_left,
+            paddle_right,
+            ball: Ball::from_configuration(config, ctx),
+            header: replay.header,
+            timeline,
+            position: 0.0,
+            playing: true,
+            speed: NORMAL_SPEED,
+            typed_tick: String::new(),
+            screen: graphics::Rect::new(0.0, 0.0, config.screen_width, config.screen_height),
+        };
+        viewer.seek(start_tick as f32);
+        viewer
+    }
+}
+
+impl<L: PaddleDraw, R: PaddleDraw> ReplayViewer<L, R> {
+    fn last_tick(&self) -> f32 {
+        self.timeline.states.len().saturating_sub(1) as f32
+    }
+
+    fn seek(&mut self, tick: f32) {
+        self.position = tick.clamp(0.0, self.last_tick());
+    }
+
+    fn shown_tick(&self) -> usize {
+        self.position.floor() as usize
+    }
+
+    fn shown_state(&self) -> GameState {
+        let tick = self.shown_tick();
+        // after a goal the ball is back in the middle, it doesn't fly there
+        let scores = &self.timeline.scores;
+        let goal = scores.get(tick) != scores.get(tick + 1);
+        match (
+            self.timeline.states.get(tick),
+            self.timeline.states.get(tick + 1),
+        ) {
+            (Some(a), Some(b)) if !goal => blend_states(a, b, self.position.fract()),
+            (Some(a), None) => *a,
+            // nothing was played
+            _ => GameState::from_configuration(&self.header.config),
+        }
+    }
+
+    fn score(&self) -> (u32, u32) {
+        self.timeline
+            .scores
+            .get(self.shown_tick())
+            .copied()
+            .unwrap_or((0, 0))
+    }
+
+    fn next_goal(&mut self) {
+        let shown = self.shown_tick() as Tick;
+        if let Some(&goal) = self.timeline.goals.iter().find(|&&goal| goal > shown) {
+            self.seek(goal as f32);
+        }
+    }
+
+    fn previous_goal(&mut self) {
+        let shown = self.shown_tick() as Tick;
+        if let Some(&goal) = self.timeline.goals.iter().rev().find(|&&goal| goal < shown) {
+            self.seek(goal as f32);
+        }
+    }
+
+    // stops playing, goes a tick forward or back
+    fn step(&mut self, ticks: f32) {
+        self.playing = false;
+        self.seek(self.position.floor() + ticks);
+    }
+
+    fn jump(&mut self, seconds: f32) {
+        self.seek(self.position + seconds * self.header.config.ticks_per_second as f32);
+    }
+
+    fn bar(&self) -> graphics::Rect {
+        graphics::Rect::new(
+            HUD_MARGIN,
+            self.screen.h - HUD_MARGIN - BAR_HEIGHT,
+            self.screen.w - 2.0 * HUD_MARGIN,
+            BAR_HEIGHT,
+        )
+    }
+
+    fn score_text(&self) -> String {
+        let name = |name: Option<PlayerName>, side: &str| {
+            name.map_or(side.to_string(), |name| name.to_string())
+        };
+        let (left, right) = self.score();
+        format!(
+            "{} {} : {} {}",
+            name(self.header.left.name, "Left"),
+            left,
+            right,
+            name(self.header.right.name, "Right")
+        )
+    }
+
+    fn status_text(&self) -> String {
+        let state = if self.playing { "playing" } else { "paused" };
+        let mut text = format!(
+            "tick {}/{}  {}x  {}",
+            self.shown_tick(),
+            self.last_tick(),
+            SPEEDS[self.speed],
+            state
+        );
+        if !self.typed_tick.is_empty() {
+            text += &format!("  go to tick: {}", self.typed_tick);
+        }
+        text
+    }
+}
+
+impl<L: PaddleDraw, R: PaddleDraw> ggez::event::EventHandler<GameError> for ReplayViewer<L, R> {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        let dt = 1.0 / DESIRED_FPS as f32;
+        while ctx.time.check_update_time(DESIRED_FPS) {
+            if self.playing {
+                let ticks = dt * self.header.config.ticks_per_second as f32 * SPEEDS[self.speed];
+                self.seek(self.position + ticks);
+                if self.position >= self.last_tick() {
+                    self.playing = false;
+                }
+            }
+        }
+        let state = self.shown_state();
+        self.ball.set_position(state.ball);
+        self.paddle_left.set_position(state.left_paddle);
+        self.paddle_right.set_position(state.right_paddle);
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let mut canvas = graphics::Canvas::from_frame(ctx, SCREEN_COLOR);
+        canvas.set_screen_coordinates(self.screen);
+        self.ball.draw(&mut canvas);
+        self.paddle_left.draw(&mut canvas);
+        self.paddle_right.draw(&mut canvas);
+
+        let mut y = HUD_MARGIN;
+        for line in [self.score_text(), self.status_text()] {
+            let text = graphics::Text::new(line);
+            let size = text.measure(ctx)?;
+            canvas.draw(
+                &text,
+                graphics::DrawParam::new()
+                    .dest(glam::vec2((self.screen.w - size.x) / 2.0, y))
+                    .color(HUD_COLOR),
+            );
+            y += size.y;
+        }
+        let bar = self.bar();
+        let mut y = bar.y - HUD_MARGIN;
+        for line in HELP.iter().rev() {
+            let text = graphics::Text::new(*line);
+            y -= text.measure(ctx)?.y;
+            canvas.draw(
+                &text,
+                graphics::DrawParam::new()
+                    .dest(glam::vec2(HUD_MARGIN, y))
+                    .color(BAR_COLOR),
+            );
+        }
+
+        // progress bar with goals marked
+        let played = bar.w * self.position / self.last_tick().max(1.0);
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest(bar.point())
+                .scale(glam::vec2(bar.w, bar.h))
+                .color(BAR_COLOR),
+        );
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest(bar.point())
+                .scale(glam::vec2(played, bar.h))
+                .color(HUD_COLOR),
+        );
+        for &goal in &self.timeline.goals {
+            let x = bar.x + bar.w * goal as f32 / self.last_tick().max(1.0);
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest(glam::vec2(x - 1.0, bar.y - BAR_HEIGHT))
+                    .scale(glam::vec2(2.0, 3.0 * BAR_HEIGHT))
+                    .color(GOAL_MARK_COLOR),
+            );
+        }
+
+        canvas.finish(ctx)?;
+        ggez::timer::yield_now();
+        Ok(())
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repeat: bool) -> GameResult {
+        match input.keycode {
+            Some(KeyCode::Space) => {
+                // playing from the end starts over
+                if !self.playing && self.position >= self.last_tick() {
+                    self.seek(0.0);
+                }
+                self.playing = !self.playing;
+            }
+            Some(KeyCode::Up) => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            Some(KeyCode::Down) => self.speed = self.speed.saturating_sub(1),
+            Some(KeyCode::Right) => self.step(1.0),
+            Some(KeyCode::Left) => self.step(-1.0),
+            Some(KeyCode::PageUp) => self.jump(JUMP_SECONDS),
+            Some(KeyCode::PageDown) => self.jump(-JUMP_SECONDS),
+            Some(KeyCode::Home) => self.seek(0.0),
+            Some(KeyCode::End) => self.seek(self.last_tick()),
+            Some(KeyCode::N) => self.next_goal(),
+            Some(KeyCode::P) => self.previous_goal(),
+            Some(KeyCode::Return | KeyCode::NumpadEnter) => {
+                if let Ok(tick) = self.typed_tick.parse::<Tick>() {
+                    self.seek(tick as f32);
+                }
+                self.typed_tick.clear();
+            }
+            Some(KeyCode::Back) => {
+                self.typed_tick.pop();
+            }
+            Some(KeyCode::Escape) => ctx.request_quit(),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> GameResult {
+        if character.is_ascii_digit() {
+            self.typed_tick.push(character);
+        }
+        Ok(())
+    }
+
+    fn mouse_button_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    ) -> GameResult {
+        // the bar is thin, so clicks a bit around it count too
+        let bar = self.bar();
+        if button == MouseButton::Left && (y - (bar.y + bar.h / 2.0)).abs() <= 3.0 * BAR_HEIGHT {
+            self.seek((x - bar.x) / bar.w * self.last_tick());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,848 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::external_bot::{play_match, Bot, BotError, ExternalBot, MatchReport, MatchSettings};
+use super::gym::{Action, PongEnv};
+use super::rating::{updated_ratings, DEFAULT_RATING};
+use crate::configuration::Configuration;
+use crate::controller::{
+    Controller, ControllerKind, Difficulty, PredictiveAi, Situation, TrackingAi,
+};
+use crate::rules::Side;
+
+// Tournaments between AIs of this crate and external bots (common/external_bot.rs),
+// played headlessly by play_match, as fast as the bots decide. AIs decide instantly.
+
+// who takes part
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entrant {
+    Ai(ControllerKind, Difficulty),
+    // command starting the bot process
+    External(String),
+}
+
+// "tracking", "predictive:hard" (difficulty defaults to normal) or "bot:<command>"
+impl FromStr for Entrant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(command) = s.strip_prefix("bot:") {
+            if command.trim().is_empty() {
+                return Err("bot: needs a command".to_string());
+            }
+            return Ok(Entrant::External(command.to_string()));
+        }
+        let (kind, difficulty) = s.split_once(':').unwrap_or((s, "normal"));
+        let kind = <ControllerKind as clap::ValueEnum>::from_str(kind, true).map_err(|_| {
+            format!(
+                "unknown entrant {}, expected tracking, predictive or bot:<command>",
+                s
+            )
+        })?;
+        if kind == ControllerKind::Human {
+            return Err("humans can't take part in a headless tournament".to_string());
+        }
+        let difficulty =
+            <Difficulty as clap::ValueEnum>::from_str(difficulty, true).map_err(|_| {
+                format!(
+                    "unknown difficulty {}, expected easy, normal or hard",
+                    difficulty
+                )
+            })?;
+        Ok(Entrant::Ai(kind, difficulty))
+    }
+}
+
+impl fmt::Display for Entrant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Entrant::Ai(kind, difficulty) => write!(
+                f,
+                "{}:{}",
+                format!("{:?}", kind).to_lowercase(),
+                format!("{:?}", difficulty).to_lowercase()
+            ),
+            Entrant::External(command) => write!(f, "bot:{}", command),
+        }
+    }
+}
+
+impl Entrant {
+    // AIs get a new seed from `seed` for every match
+    pub fn bot(&self, seed: u64) -> Result<Box<dyn Bot>, BotError> {
+        Ok(match self {
+            Entrant::Ai(kind, difficulty) => Box::new(AiBot::new(*kind, *difficulty, seed)),
+            Entrant::External(command) => Box::new(ExternalBot::spawn(command)?),
+        })
+    }
+}
+
+// An AI of this crate seated as a bot, it moves the paddle of its side.
+pub struct AiBot {
+    kind: ControllerKind,
+    difficulty: Difficulty,
+    rng: StdRng,
+    controller: Option<Box<dyn Controller>>,
+    side: Side,
+    dt: f32,
+    decision: Action,
+}
+
+impl AiBot {
+    pub fn new(kind: ControllerKind, difficulty: Difficulty, seed: u64) -> Self {
+        Self {
+            kind,
+            difficulty,
+            rng: StdRng::seed_from_u64(seed),
+            controller: None,
+            side: Side::Left,
+            dt: 0.0,
+            decision: Action::Stay,
+        }
+    }
+}
+
+impl Bot for AiBot {
+    fn start(
+        &mut self,
+        side: Side,
+        configuration: &Configuration,
+        _timeout: Duration,
+    ) -> Result<(), BotError> {
+        let seed = self.rng.gen();
+        self.controller = Some(match self.kind {
+            ControllerKind::Tracking => Box::new(TrackingAi::new(self.difficulty, seed)),
+            ControllerKind::Predictive => Box::new(PredictiveAi::new(self.difficulty, seed)),
+            ControllerKind::Human => {
+                return Err(BotError::Protocol(
+                    "a human can't play headless".to_string(),
+                ))
+            }
+        });
+        self.side = side;
+        self.dt = 1.0 / configuration.ticks_per_second as f32;
+        Ok(())
+    }
+
+    fn request(&mut self, env: &PongEnv, _budget: Duration) -> Result<(), BotError> {
+        let pong = env.pong();
+        let paddle = match self.side {
+            Side::Left => &pong.left_paddle,
+            Side::Right => &pong.right_paddle,
+        };
+        let situation = Situation::new(&pong.ball, paddle, self.dt);
+        let controller = self.controller.as_mut().expect("started before requests");
+        self.decision = Action::from_keys(controller.keys(&situation));
+        Ok(())
+    }
+
+    fn action(&mut self) -> Result<Action, BotError> {
+        Ok(self.decision)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    // everyone against everyone
+    RoundRobin,
+    // every round entrants with similar scores meet, nobody twice if it can be helped
+    Swiss,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TournamentSettings {
+    pub format: Format,
+    // rounds of a swiss tournament, enough to find a single winner if None
+    pub rounds: Option<u32>,
+    // matches of every pairing, the entrants change sides after each
+    pub games: u32,
+    pub matches: MatchSettings,
+    // serves of the n-th match played are picked by seed + n, AIs are seeded from it too
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Game {
+    pub round: u32,
+    pub left: String,
+    pub right: String,
+    pub report: MatchReport,
+}
+
+// Everything played, in order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Tournament {
+    pub names: Vec<String>,
+    pub games: Vec<Game>,
+    // (round, entrant) who had no opponent that round, it scores 1 but isn't counted as a win
+    pub byes: Vec<(u32, String)>,
+}
+
+// every entrant's name, repeated entrants get a number
+fn unique_names(entrants: &[Entrant]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for entrant in entrants {
+        let name = entrant.to_string();
+        let same = names
+            .iter()
+            .filter(|other| **other == name || other.starts_with(&format!("{} #", name)))
+            .count();
+        names.push(if same == 0 {
+            name
+        } else {
+            format!("{} #{}", name, same + 1)
+        });
+    }
+    names
+}
+
+// Round robin by the circle method: the first one stays, the others turn around the table,
+// None stands for the bye of an odd entrant count.
+fn round_robin_rounds(count: usize) -> Vec<Vec<(usize, usize)>> {
+    let mut table: Vec<Option<usize>> = (0..count).map(Some).collect();
+    if count % 2 == 1 {
+        table.push(None);
+    }
+    let size = table.len();
+    let mut rounds = Vec::new();
+    for _ in 1..size {
+        let round = (0..size / 2)
+            .filter_map(|i| match (table[i], table[size - 1 - i]) {
+                (Some(a), Some(b)) => Some((a, b)),
+                _ => None,
+            })
+            .collect();
+        rounds.push(round);
+        table[1..].rotate_right(1);
+    }
+    rounds
+}
+
+impl Tournament {
+    // Plays the whole tournament, progress goes to stderr.
+    // Fails only if a bot can't be started at all.
+    pub fn play(
+        entrants: &[Entrant],
+        configuration: &Configuration,
+        settings: &TournamentSettings,
+    ) -> Result<Self, (String, BotError)> {
+        let names = unique_names(entrants);
+        let mut seeds = StdRng::seed_from_u64(settings.seed);
+        let mut bots = Vec::new();
+        for (entrant, name) in entrants.iter().zip(&names) {
+            bots.push(entrant.bot(seeds.gen()).map_err(|e| (name.clone(), e))?);
+        }
+        let mut tournament = Tournament {
+            names,
+            ..Tournament::default()
+        };
+
+        match settings.format {
+            Format::RoundRobin => {
+                for (round, pairs) in round_robin_rounds(bots.len()).into_iter().enumerate() {
+                    for (a, b) in pairs {
+                        tournament.play_pairing(
+                            round as u32 + 1,
+                            &mut bots,
+                            (a, b),
+                            configuration,
+                            settings,
+                        );
+                    }
+                }
+            }
+            Format::Swiss => {
+                let rounds = settings
+                    .rounds
+                    .unwrap_or_else(|| bots.len().next_power_of_two().trailing_zeros().max(1));
+                let mut met = HashSet::new();
+                for round in 1..=rounds {
+                    let (pairs, bye) = tournament.swiss_pairs(&met);
+                    if let Some(bye) = bye {
+                        tournament.byes.push((round, tournament.names[bye].clone()));
+                    }
+                    for (a, b) in pairs {
+                        met.insert((a.min(b), a.max(b)));
+                        tournament.play_pairing(round, &mut bots, (a, b), configuration, settings);
+                    }
+                }
+            }
+        }
+        Ok(tournament)
+    }
+
+    fn play_pairing(
+        &mut self,
+        round: u32,
+        bots: &mut [Box<dyn Bot>],
+        (a, b): (usize, usize),
+        configuration: &Configuration,
+        settings: &TournamentSettings,
+    ) {
+        for game in 0..settings.games {
+            let (left, right) = if game % 2 == 0 { (a, b) } else { (b, a) };
+            // both are taken out of the slice at once
+            let (first, second) = bots.split_at_mut(left.max(right));
+            let (left_bot, right_bot) = if left < right {
+                (&mut first[left], &mut second[0])
+            } else {
+                (&mut second[0], &mut first[right])
+            };
+            let seed = settings.seed.wrapping_add(self.games.len() as u64);
+            let report = play_match(
+                configuration,
+                left_bot.as_mut(),
+                right_bot.as_mut(),
+                seed,
+                &settings.matches,
+            );
+            eprintln!(
+                "Round {}: {} {} : {} {}",
+                round, self.names[left], report.score.0, report.score.1, self.names[right]
+            );
+            self.games.push(Game {
+                round,
+                left: self.names[left].clone(),
+                right: self.names[right].clone(),
+                report,
+            });
+        }
+    }
+
+    // pairs of the next swiss round and who has a bye, see swiss_pairing
+    fn swiss_pairs(&self, met: &HashSet<(usize, usize)>) -> (Vec<(usize, usize)>, Option<usize>) {
+        let standings = self.standings();
+        let mut order: Vec<usize> = (0..self.names.len()).collect();
+        // stable, so equal scores keep the order of the entrants
+        order.sort_by(|&a, &b| standings[b].score.total_cmp(&standings[a].score));
+        let had_bye: Vec<bool> = standings.iter().map(|s| s.byes > 0).collect();
+        swiss_pairing(&order, &had_bye, met)
+    }
+}
+
+// Pairs entrants of `order` (best score first) so that nobody meets anyone twice, each with
+// the best one it can; with an odd count the lowest one who hasn't had a bye yet, who still
+// lets everyone else be paired, has a bye. If there's no such pairing, everyone gets the next
+// one they haven't met (or just the next one) and the lowest one without a bye has it.
+fn swiss_pairing(
+    order: &[usize],
+    had_bye: &[bool],
+    met: &HashSet<(usize, usize)>,
+) -> (Vec<(usize, usize)>, Option<usize>) {
+    // positions of the bye candidates, those who had one last, the lowest first among equals
+    let candidates: Vec<Option<usize>> = if order.len() % 2 == 1 {
+        let mut positions: Vec<usize> = (0..order.len()).rev().collect();
+        positions.sort_by_key(|&position| had_bye[order[position]]);
+        positions.into_iter().map(Some).collect()
+    } else {
+        vec![None]
+    };
+    let mut failed = HashSet::new();
+    for &position in &candidates {
+        let mut rest = order.to_vec();
+        let bye = position.map(|position| rest.remove(position));
+        if let Some(pairs) = pair_unmet(&rest, met, &mut failed) {
+            return (pairs, bye);
+        }
+    }
+
+    let mut rest = order.to_vec();
+    let bye = candidates[0].map(|position| rest.remove(position));
+    let mut pairs = Vec::new();
+    while !rest.is_empty() {
+        let a = rest.remove(0);
+        let position = rest
+            .iter()
+            .position(|&b| !met.contains(&(a.min(b), a.max(b))))
+            .unwrap_or(0);
+        pairs.push((a, rest.remove(position)));
+    }
+    (pairs, bye)
+}
+
+// Pairs everyone in `order` with someone they haven't met, the first one with the best one
+// possible and so on, going back when the rest can't be paired; None if it can't be done.
+// `failed` remembers the remainders which couldn't be paired.
+fn pair_unmet(
+    order: &[usize],
+    met: &HashSet<(usize, usize)>,
+    failed: &mut HashSet<Vec<usize>>,
+) -> Option<Vec<(usize, usize)>> {
+    let Some((&a, others)) = order.split_first() else {
+        return Some(Vec::new());
+    };
+    if failed.contains(order) {
+        return None;
+    }
+    for (position, &b) in others.iter().enumerate() {
+        if met.contains(&(a.min(b), a.max(b))) {
+            continue;
+        }
+        let mut rest = others.to_vec();
+        rest.remove(position);
+        if let Some(mut pairs) = pair_unmet(&rest, met, failed) {
+            pairs.insert(0, (a, b));
+            return Some(pairs);
+        }
+    }
+    failed.insert(order.to_vec());
+    None
+}
+
+// (left, right) score of a match: 1 win, 0.5 undecided, 0 loss
+fn match_score(winner: Option<Side>) -> (f64, f64) {
+    match winner {
+        Some(Side::Left) => (1.0, 0.0),
+        Some(Side::Right) => (0.0, 1.0),
+        None => (0.5, 0.5),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Standing {
+    pub name: String,
+    pub played: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub byes: u32,
+    // wins and byes count 1, draws (matches ended by the tick limit) 0.5
+    pub score: f64,
+    pub points_for: u32,
+    pub points_against: u32,
+    // paddle hits per point in the entrant's matches
+    pub average_rally: f64,
+    // estimated from all matches in order, everyone starting at the default rating
+    pub elo: f64,
+    pub forfeits: u32,
+    pub timeouts: u32,
+    pub invalid: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HeadToHead {
+    pub name: String,
+    pub opponent: String,
+    pub played: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    // draws count half
+    pub win_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Results {
+    // best first
+    pub standings: Vec<Standing>,
+    pub head_to_head: Vec<HeadToHead>,
+    // paddle hits per point over the whole tournament
+    pub average_rally: f64,
+    pub games: Vec<Game>,
+}
+
+fn ratio(numerator: u32, denominator: u32) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+impl Tournament {
+    fn index(&self, name: &str) -> usize {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .expect("games are between entrants")
+    }
+
+    // in the order of the entrants
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = self
+            .names
+            .iter()
+            .map(|name| Standing {
+                name: name.clone(),
+                played: 0,
+                wins: 0,
+                draws: 0,
+                losses: 0,
+                byes: 0,
+                score: 0.0,
+                points_for: 0,
+                points_against: 0,
+                average_rally: 0.0,
+                elo: DEFAULT_RATING,
+                forfeits: 0,
+                timeouts: 0,
+                invalid: 0,
+            })
+            .collect();
+        // hits of the entrant's matches
+        let mut hits = vec![0; self.names.len()];
+        for game in &self.games {
+            let report = &game.report;
+            let (left, right) = (self.index(&game.left), self.index(&game.right));
+            let (left_score, _) = match_score(report.winner);
+            let (left_elo, right_elo) =
+                updated_ratings(standings[left].elo, standings[right].elo, left_score);
+            standings[left].elo = left_elo;
+            standings[right].elo = right_elo;
+            for (i, side, points, timeouts, invalid) in [
+                (
+                    left,
+                    Side::Left,
+                    report.score,
+                    report.timeouts.0,
+                    report.invalid.0,
+                ),
+                (
+                    right,
+                    Side::Right,
+                    (report.score.1, report.score.0),
+                    report.timeouts.1,
+                    report.invalid.1,
+                ),
+            ] {
+                let standing = &mut standings[i];
+                standing.played += 1;
+                match report.winner {
+                    Some(winner) if winner == side => standing.wins += 1,
+                    Some(_) => standing.losses += 1,
+                    None => standing.draws += 1,
+                }
+                standing.points_for += points.0;
+                standing.points_against += points.1;
+                standing.timeouts += timeouts;
+                standing.invalid += invalid;
+                if report.forfeit.as_ref().is_some_and(|f| f.side == side) {
+                    standing.forfeits += 1;
+                }
+                hits[i] += report.hits;
+            }
+        }
+        for (_, name) in &self.byes {
+            standings[self.index(name)].byes += 1;
+        }
+        for (standing, hits) in standings.iter_mut().zip(hits) {
+            standing.score = (standing.wins + standing.byes) as f64 + standing.draws as f64 / 2.0;
+            standing.average_rally = ratio(hits, standing.points_for + standing.points_against);
+        }
+        standings
+    }
+
+    // every ordered pair of entrants who met
+    pub fn head_to_head(&self) -> Vec<HeadToHead> {
+        let mut pairs = Vec::new();
+        for name in &self.names {
+            for opponent in &self.names {
+                let mut record = HeadToHead {
+                    name: name.clone(),
+                    opponent: opponent.clone(),
+                    played: 0,
+                    wins: 0,
+                    draws: 0,
+                    losses: 0,
+                    win_rate: 0.0,
+                };
+                for game in &self.games {
+                    let side = if (&game.left, &game.right) == (name, opponent) {
+                        Side::Left
+                    } else if (&game.right, &game.left) == (name, opponent) {
+                        Side::Right
+                    } else {
+                        continue;
+                    };
+                    record.played += 1;
+                    match game.report.winner {
+                        Some(winner) if winner == side => record.wins += 1,
+                        Some(_) => record.losses += 1,
+                        None => record.draws += 1,
+                    }
+                }
+                if record.played > 0 {
+                    record.win_rate =
+                        (record.wins as f64 + record.draws as f64 / 2.0) / record.played as f64;
+                    pairs.push(record);
+                }
+            }
+        }
+        pairs
+    }
+
+    pub fn results(&self) -> Results {
+        let mut standings = self.standings();
+        standings.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.elo.total_cmp(&a.elo)));
+        let hits = self.games.iter().map(|game| game.report.hits).sum();
+        let points = self
+            .games
+            .iter()
+            .map(|game| game.report.score.0 + game.report.score.1)
+            .sum();
+        Results {
+            standings,
+            head_to_head: self.head_to_head(),
+            average_rally: ratio(hits, points),
+            games: self.games.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    // tables for people
+    Text,
+    Json,
+    // standings, an empty line, then head to head
+    Csv,
+}
+
+// quoted if it needs to be
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Results {
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.text(),
+            OutputFormat::Json => serde_json::to_string_pretty(self).expect("results serialize"),
+            OutputFormat::Csv => self.csv(),
+        }
+    }
+
+    fn text(&self) -> String {
+        let width = self
+            .standings
+            .iter()
+            .map(|s| s.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        let mut out = format!(
+            "{:>4}  {:<width$}  {:>6} {:>4} {:>4} {:>4} {:>6}  {:>9}  {:>6}  {:>6}\n",
+            "#", "Name", "Played", "W", "D", "L", "Score", "Points", "Rally", "Elo"
+        );
+        for (rank, s) in self.standings.iter().enumerate() {
+            out += &format!(
+                "{:>4}  {:<width$}  {:>6} {:>4} {:>4} {:>4} {:>6.1}  {:>4}:{:<4}  {:>6.2}  {:>6.0}\n",
+                rank + 1,
+                s.name,
+                s.played,
+                s.wins,
+                s.draws,
+                s.losses,
+                s.score,
+                s.points_for,
+                s.points_against,
+                s.average_rally,
+                s.elo
+            );
+        }
+        out += &format!(
+            "\nAverage rally: {:.2} hits per point\n",
+            self.average_rally
+        );
+
+        // win rate of the row against the column, by rank
+        out += "\nHead to head (row's win rate against column):\n";
+        out += &format!("{:>4}", "");
+        for rank in 1..=self.standings.len() {
+            out += &format!(" {:>5}", rank);
+        }
+        out += "\n";
+        for (rank, s) in self.standings.iter().enumerate() {
+            out += &format!("{:>4}", rank + 1);
+            for opponent in &self.standings {
+                let rate = self
+                    .head_to_head
+                    .iter()
+                    .find(|h| h.name == s.name && h.opponent == opponent.name)
+                    .map_or("-".to_string(), |h| format!("{:.2}", h.win_rate));
+                out += &format!(" {:>5}", rate);
+            }
+            out += "\n";
+        }
+
+        let faults: Vec<_> = self
+            .standings
+            .iter()
+            .filter(|s| s.forfeits + s.timeouts + s.invalid > 0)
+            .collect();
+        if !faults.is_empty() {
+            out += "\n";
+        }
+        for s in faults {
+            out += &format!(
+                "{}: {} forfeits, {} ticks without an answer in time, {} invalid answers\n",
+                s.name, s.forfeits, s.timeouts, s.invalid
+            );
+        }
+        out
+    }
+
+    fn csv(&self) -> String {
+        let mut out = String::from(
+            "rank,name,played,wins,draws,losses,byes,score,points_for,points_against,average_rally,elo,forfeits,timeouts,invalid\n",
+        );
+        for (rank, s) in self.standings.iter().enumerate() {
+            out += &format!(
+                "{},{},{},{},{},{},{},{},{},{},{:.4},{:.1},{},{},{}\n",
+                rank + 1,
+                csv_field(&s.name),
+                s.played,
+                s.wins,
+                s.draws,
+                s.losses,
+                s.byes,
+                s.score,
+                s.points_for,
+                s.points_against,
+                s.average_rally,
+                s.elo,
+                s.forfeits,
+                s.timeouts,
+                s.invalid
+            );
+        }
+        out += "\nname,opponent,played,wins,draws,losses,win_rate\n";
+        for h in &self.head_to_head {
+            out += &format!(
+                "{},{},{},{},{},{},{:.4}\n",
+                csv_field(&h.name),
+                csv_field(&h.opponent),
+                h.played,
+                h.wins,
+                h.draws,
+                h.losses,
+                h.win_rate
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn met(pairs: &[(usize, usize)]) -> HashSet<(usize, usize)> {
+        pairs.iter().map(|&(a, b)| (a.min(b), a.max(b))).collect()
+    }
+
+    // tracking:easy predictive:hard tracking:hard predictive:easy tracking --seed 7
+    // before round 3, 4 and 2 have had byes
+    #[test]
+    fn swiss_goes_back_instead_of_a_rematch() {
+        let met = met(&[(0, 1), (2, 3), (1, 3), (0, 4)]);
+        let had_bye = [false, false, true, false, true];
+        let (pairs, bye) = swiss_pairing(&[1, 3, 4, 2, 0], &had_bye, &met);
+        assert_eq!(bye, Some(0));
+        assert_eq!(pairs, vec![(1, 2), (3, 4)]);
+    }
+
+    #[test]
+    fn round_robin_pairs_everyone_once() {
+        for count in 2..=9 {
+            let rounds = round_robin_rounds(count);
+            let mut pairs: Vec<(usize, usize)> = rounds
+                .iter()
+                .flatten()
+                .map(|&(a, b)| (a.min(b), a.max(b)))
+                .collect();
+            pairs.sort();
+            let expected: Vec<(usize, usize)> = (0..count)
+                .flat_map(|a| (a + 1..count).map(move |b| (a, b)))
+                .collect();
+            assert_eq!(pairs, expected, "{} entrants", count);
+            // nobody plays twice in a round
+            for round in &rounds {
+                let mut seated: Vec<usize> = round.iter().flat_map(|&(a, b)| [a, b]).collect();
+                seated.sort();
+                seated.dedup();
+                assert_eq!(seated.len(), 2 * round.len());
+                assert_eq!(round.len(), count / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn swiss_never_rematches_when_it_can_be_avoided() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for count in [6, 7, 8, 9, 10] {
+            let mut met = HashSet::new();
+            let mut had_bye = vec![false; count];
+            // after fewer rounds than half the entrants there is always a way
+            for _ in 0..count / 2 - 1 {
+                let mut order: Vec<usize> = (0..count).collect();
+                for i in (1..count).rev() {
+                    order.swap(i, rng.gen_range(0..=i));
+                }
+                let (pairs, bye) = swiss_pairing(&order, &had_bye, &met);
+                assert_eq!(pairs.len(), count / 2);
+                for (a, b) in pairs {
+                    assert!(
+                        met.insert((a.min(b), a.max(b))),
+                        "rematch of {} and {}",
+                        a,
+                        b
+                    );
+                }
+                if let Some(bye) = bye {
+                    assert!(!had_bye[bye]);
+                    had_bye[bye] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bye_scores_but_isnt_a_win() {
+        let report = MatchReport {
+            seed: 0,
+            score: (11, 3),
+            winner: Some(Side::Left),
+            ticks: 100,
+            forfeit: None,
+            timeouts: (0, 0),
+            invalid: (0, 0),
+            hits: 20,
+        };
+        let tournament = Tournament {
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            games: vec![Game {
+                round: 1,
+                left: "a".to_string(),
+                right: "b".to_string(),
+                report,
+            }],
+            byes: vec![(1, "c".to_string())],
+        };
+        let standings = tournament.standings();
+        assert_eq!((standings[0].wins, standings[0].score), (1, 1.0));
+        assert_eq!((standings[1].losses, standings[1].score), (1, 0.0));
+        assert_eq!(standings[2].wins, 0);
+        assert_eq!(standings[2].played, 0);
+        assert_eq!(standings[2].byes, 1);
+        assert_eq!(standings[2].score, 1.0);
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("tracking:easy"), "tracking:easy");
+        assert_eq!(csv_field("python3 bot.py"), "python3 bot.py");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -1,4 +1,34 @@
-use ggez::{glam, graphics};
+use serde::{Deserialize, Serialize};
+
+use super::rules::MatchRules;
+use std::{fmt, path::Path};
+
+// plain rgba colour, so that the simulation doesn't depend on ggez
+// components are in [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    #[serde(default = "opaque")]
+    pub a: f32,
+}
+
+fn opaque() -> f32 {
+    1.0
+}
+
+impl Color {
+    pub const WHITE: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+    pub const BLACK: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+    pub const RED: Color = Color::new(1.0, 0.0, 0.0, 1.0);
+    pub const GREEN: Color = Color::new(0.0, 1.0, 0.0, 1.0);
+    pub const BLUE: Color = Color::new(0.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+}
 
 pub const SCREEN_WIDTH: f32 = 800.0;
 pub const SCREEN_HEIGHT: f32 = 600.0;
@@ -10,25 +40,46 @@ const PADDLE_SPEED: f32 = 200.0;
 
 const PADDLE_TO_BALL_SPEEDUP: f32 = 1.1;
 
-const LEFT_PADDLE_COLOR: graphics::Color = graphics::Color::RED;
-const RIGHT_PADDLE_COLOR: graphics::Color = graphics::Color::BLUE;
+const TICKS_PER_SECOND: u32 = 30;
+
+const LEFT_PADDLE_COLOR: Color = Color::RED;
+const RIGHT_PADDLE_COLOR: Color = Color::BLUE;
 
 const INITIAL_BALL_VELOCITY: glam::Vec2 = glam::vec2(200.0, 200.0);
 const BALL_RADIUS: f32 = 15.0;
-const BALL_COLOR: graphics::Color = graphics::Color::WHITE;
+const BALL_COLOR: Color = Color::WHITE;
+
+// how the physics does the maths which isn't the same on every platform
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Physics {
+    // sin and cos of the standard library, fast, but may differ in the last bits between
+    // platforms, so a game re-simulated elsewhere can drift apart
+    #[default]
+    Native,
+    // only IEEE 754 basic operations, bit-identical everywhere (see game/deterministic.rs)
+    Deterministic,
+}
 
+// every field is optional in a configuration file, missing ones take default values
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Configuration {
     pub screen_width: f32,
     pub screen_height: f32,
     pub ball_radius: f32,
-    pub ball_color: ggez::graphics::Color,
+    pub ball_color: Color,
     pub ball_initial_velocity: glam::Vec2,
     pub paddle_width: f32,
     pub paddle_height: f32,
     pub paddle_speed: f32,
     pub paddle_to_ball_speedup: f32,
-    pub left_paddle_color: ggez::graphics::Color,
-    pub right_paddle_color: ggez::graphics::Color,
+    // simulation rate of the online game
+    pub ticks_per_second: u32,
+    pub left_paddle_color: Color,
+    pub right_paddle_color: Color,
+    pub physics: Physics,
+    pub rules: MatchRules,
 }
 
 impl Default for Configuration {
@@ -45,10 +96,298 @@ impl Default for Configuration {
             left_paddle_color: LEFT_PADDLE_COLOR,
             right_paddle_color: RIGHT_PADDLE_COLOR,
             paddle_to_ball_speedup: PADDLE_TO_BALL_SPEEDUP,
+            ticks_per_second: TICKS_PER_SECOND,
+            physics: Physics::default(),
+            rules: MatchRules::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigurationError {
+    Io(std::io::Error),
+    Parse(String),
+    UnknownFormat(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigurationError::Io(e) => write!(f, "couldn't read configuration file: {}", e),
+            ConfigurationError::Parse(e) => write!(f, "couldn't parse configuration: {}", e),
+            ConfigurationError::UnknownFormat(ext) => write!(
+                f,
+                "unknown configuration format {:?}, expected .toml or .json",
+                ext
+            ),
+            ConfigurationError::Invalid(e) => write!(f, "invalid configuration: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigurationError {}
+
+impl Configuration {
+    // gameplay values are taken from `server`, only the looks stay local
+    pub fn with_gameplay_of(&self, server: &Configuration) -> Self {
+        Self {
+            ball_color: self.ball_color,
+            left_paddle_color: self.left_paddle_color,
+            right_paddle_color: self.right_paddle_color,
+            ..*server
+        }
+    }
+
+    // format is chosen by the file extension
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigurationError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(ConfigurationError::Io)?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let config: Configuration = match extension {
+            "toml" => {
+                toml::from_str(&contents).map_err(|e| ConfigurationError::Parse(e.to_string()))?
+            }
+            "json" => serde_json::from_str(&contents)
+                .map_err(|e| ConfigurationError::Parse(e.to_string()))?,
+            _ => return Err(ConfigurationError::UnknownFormat(extension.to_string())),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigurationError> {
+        let invalid = |msg: String| Err(ConfigurationError::Invalid(msg));
+        let positive = [
+            ("screen_width", self.screen_width),
+            ("screen_height", self.screen_height),
+            ("ball_radius", self.ball_radius),
+            ("paddle_width", self.paddle_width),
+            ("paddle_height", self.paddle_height),
+        ];
+        for (name, value) in positive {
+            if !value.is_finite() || value <= 0.0 {
+                return invalid(format!("{} must be positive, got {}", name, value));
+            }
+        }
+        let non_negative = [
+            ("paddle_speed", self.paddle_speed),
+            ("paddle_to_ball_speedup", self.paddle_to_ball_speedup),
+            ("rules.serve_delay", self.rules.serve_delay),
+        ];
+        for (name, value) in non_negative {
+            if !value.is_finite() || value < 0.0 {
+                return invalid(format!("{} can't be negative, got {}", name, value));
+            }
+        }
+        if !(1..=1000).contains(&self.ticks_per_second) {
+            return invalid(format!(
+                "ticks_per_second must be between 1 and 1000, got {}",
+                self.ticks_per_second
+            ));
+        }
+        if self.rules.points_to_win == 0 {
+            return invalid("rules.points_to_win must be at least 1".to_string());
+        }
+        if self.paddle_height > self.screen_height {
+            return invalid(format!(
+                "paddle_height ({}) is bigger than screen_height ({})",
+                self.paddle_height, self.screen_height
+            ));
         }
+        if 2.0 * self.paddle_width >= self.screen_width {
+            return invalid(format!(
+                "two paddles of paddle_width {} don't fit in screen_width {}",
+                self.paddle_width, self.screen_width
+            ));
+        }
+        if 2.0 * self.ball_radius >= self.screen_height.min(self.screen_width) {
+            return invalid(format!(
+                "ball_radius {} doesn't fit on {}x{} screen",
+                self.ball_radius, self.screen_width, self.screen_height
+            ));
+        }
+        if !self.ball_initial_velocity.is_finite() || self.ball_initial_velocity.x == 0.0 {
+            return invalid(format!(
+                "ball_initial_velocity must be finite and move horizontally, got {}",
+                self.ball_initial_velocity
+            ));
+        }
+        let colors = [
+            ("ball_color", self.ball_color),
+            ("left_paddle_color", self.left_paddle_color),
+            ("right_paddle_color", self.right_paddle_color),
+        ];
+        for (name, c) in colors {
+            if [c.r, c.g, c.b, c.a]
+                .iter()
+                .any(|x| !(0.0..=1.0).contains(x))
+            {
+                return invalid(format!(
+                    "{} components must be in [0, 1], got {:?}",
+                    name, c
+                ));
+            }
+        }
+        Ok(())
     }
 }
 
 pub trait FromConfiguration {
     fn from_configuration(config: &Configuration) -> Self;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_toml(text: &str) -> Result<Configuration, ConfigurationError> {
+        toml::from_str(text).map_err(|e| ConfigurationError::Parse(e.to_string()))
+    }
+
+    fn invalid_message(text: &str) -> String {
+        let config = parse_toml(text).unwrap();
+        match config.validate() {
+            Err(ConfigurationError::Invalid(message)) => message,
+            other => panic!("{:?} validated as {:?}", text, other),
+        }
+    }
+
+    fn write_temp(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn defaults_and_example_are_valid() {
+        Configuration::default().validate().unwrap();
+        let example =
+            Configuration::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/config.example.toml"))
+                .unwrap();
+        assert_eq!(example.screen_width, 800.0);
+        assert_eq!(example.rules.points_to_win, 11);
+    }
+
+    #[test]
+    fn missing_fields_take_defaults() {
+        let config = parse_toml("paddle_speed = 300.0\n[rules]\npoints_to_win = 5").unwrap();
+        let defaults = Configuration::default();
+        assert_eq!(config.paddle_speed, 300.0);
+        assert_eq!(config.rules.points_to_win, 5);
+        assert_eq!(config.screen_height, defaults.screen_height);
+        assert_eq!(config.rules.win_by_two, defaults.rules.win_by_two);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid_fields_are_named() {
+        let cases = [
+            ("screen_width = 0.0", "screen_width"),
+            ("screen_height = -600.0", "screen_height"),
+            ("ball_radius = -1.0", "ball_radius"),
+            ("paddle_width = 0.0", "paddle_width"),
+            ("paddle_height = -5.0", "paddle_height"),
+            ("paddle_speed = -1.0", "paddle_speed"),
+            ("paddle_to_ball_speedup = -0.5", "paddle_to_ball_speedup"),
+            ("ticks_per_second = 0", "ticks_per_second"),
+            ("ticks_per_second = 5000", "ticks_per_second"),
+            ("[rules]\npoints_to_win = 0", "rules.points_to_win"),
+            ("[rules]\nserve_delay = -1.0", "rules.serve_delay"),
+            (
+                "screen_height = 600.0\npaddle_height = 601.0",
+                "paddle_height",
+            ),
+            ("screen_width = 800.0\npaddle_width = 400.0", "paddle_width"),
+            ("screen_height = 600.0\nball_radius = 300.0", "ball_radius"),
+            (
+                "ball_initial_velocity = [0.0, 200.0]",
+                "ball_initial_velocity",
+            ),
+            ("ball_color = { r = 1.5, g = 0.0, b = 0.0 }", "ball_color"),
+            (
+                "left_paddle_color = { r = 0.0, g = -0.1, b = 0.0 }",
+                "left_paddle_color",
+            ),
+            (
+                "right_paddle_color = { r = 0.0, g = 0.0, b = 0.0, a = 2.0 }",
+                "right_paddle_color",
+            ),
+        ];
+        for (text, field) in cases {
+            let message = invalid_message(text);
+            assert!(
+                message.contains(field),
+                "{:?} gave {:?}, which doesn't mention {}",
+                text,
+                message,
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        for text in [
+            "screen_widht = 800.0",
+            "[rules]\npoints_to_wni = 11",
+            "[extra]\nx = 1",
+        ] {
+            assert!(
+                matches!(parse_toml(text), Err(ConfigurationError::Parse(_))),
+                "{:?} was accepted",
+                text
+            );
+        }
+        let json = serde_json::from_str::<Configuration>(r#"{"ball_radious": 10.0}"#);
+        assert!(json.is_err());
+    }
+
+    #[test]
+    fn format_follows_extension() {
+        let toml_path = write_temp("config.toml", "paddle_height = 120.0\n");
+        let config = Configuration::from_file(&toml_path).unwrap();
+        assert_eq!(config.paddle_height, 120.0);
+
+        let json_path = write_temp(
+            "config.json",
+            r#"{"paddle_height": 90.0, "ball_initial_velocity": [-150.0, 10.0], "rules": {"serve": "to_conceder"}}"#,
+        );
+        let config = Configuration::from_file(&json_path).unwrap();
+        assert_eq!(config.paddle_height, 90.0);
+        assert_eq!(config.ball_initial_velocity, glam::vec2(-150.0, 10.0));
+
+        // toml contents behind a .json name are parsed as json and fail
+        let mislabeled = write_temp("mislabeled.json", "paddle_height = 120.0\n");
+        assert!(matches!(
+            Configuration::from_file(&mislabeled),
+            Err(ConfigurationError::Parse(_))
+        ));
+
+        let yaml_path = write_temp("config.yaml", "paddle_height: 120.0\n");
+        match Configuration::from_file(&yaml_path) {
+            Err(ConfigurationError::UnknownFormat(extension)) => assert_eq!(extension, "yaml"),
+            other => panic!("yaml gave {:?}", other),
+        }
+
+        // a file that parses is still validated
+        let invalid_path = write_temp("invalid.toml", "ball_radius = -1.0\n");
+        assert!(matches!(
+            Configuration::from_file(&invalid_path),
+            Err(ConfigurationError::Invalid(_))
+        ));
+
+        for path in [toml_path, json_path, mislabeled, yaml_path, invalid_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let missing = std::env::temp_dir().join(format!("{}-missing.toml", std::process::id()));
+        assert!(matches!(
+            Configuration::from_file(missing),
+            Err(ConfigurationError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,315 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::ball::BallAbstract;
+use super::paddle::Paddle;
+use super::paddle_like::PaddleLike;
+#[cfg(feature = "graphics")]
+use super::rules::Side;
+#[cfg(feature = "graphics")]
+use ggez::input::keyboard::KeyCode;
+
+// what a controller sees of the game before a step
+pub struct Situation<'a> {
+    pub ball: &'a BallAbstract,
+    // centre of the controlled paddle
+    pub paddle: glam::Vec2,
+    pub paddle_speed: f32,
+    // length of the coming step
+    pub dt: f32,
+}
+
+impl<'a> Situation<'a> {
+    pub fn new(ball: &'a BallAbstract, paddle: &Paddle<impl PaddleLike>, dt: f32) -> Self {
+        Self {
+            ball,
+            paddle: paddle.get_position(),
+            paddle_speed: paddle.speed,
+            dt,
+        }
+    }
+}
+
+// Moves one paddle: whatever is behind it, a person or an AI, it only holds the keys.
+pub trait Controller {
+    // keys held during the coming step, as (up, down)
+    fn keys(&mut self, situation: &Situation) -> (bool, bool);
+
+    #[cfg(feature = "graphics")]
+    fn key_event(&mut self, _key: KeyCode, _pressed: bool) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ControllerKind {
+    Human,
+    // follows the ball up and down
+    Tracking,
+    // goes where the ball will cross its paddle
+    Predictive,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+// how well an AI plays
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AiSettings {
+    // seconds between looking at the ball, in between the AI goes for what it saw last
+    pub reaction: f32,
+    // biggest random miss of the aimed point, in pixels
+    pub aim_error: f32,
+    // the paddle isn't moved while the aimed point is that close to its centre
+    pub dead_zone: f32,
+}
+
+impl Difficulty {
+    pub fn settings(self) -> AiSettings {
+        match self {
+            Difficulty::Easy => AiSettings {
+                reaction: 0.4,
+                aim_error: 60.0,
+                dead_zone: 20.0,
+            },
+            Difficulty::Normal => AiSettings {
+                reaction: 0.2,
+                aim_error: 30.0,
+                dead_zone: 10.0,
+            },
+            Difficulty::Hard => AiSettings {
+                reaction: 0.05,
+                aim_error: 5.0,
+                dead_zone: 2.0,
+            },
+        }
+    }
+}
+
+// Where the ball's centre will be, vertically, when it gets to `x`, with the bounces off
+// the walls it makes on the way, None if it's moving away from `x`.
+// The ball is simulated by BallAbstract::update_different in steps of `step` seconds.
+pub fn forecast_crossing(ball: &BallAbstract, x: f32, step: f32) -> Option<f32> {
+    let velocity = ball.get_velocity();
+    let mut time = (x - ball.get_position().x) / velocity.x;
+    if !time.is_finite() || time <= 0.0 {
+        return None;
+    }
+    let mut probe = ball.clone();
+    while time > 0.0 {
+        let dt = step.min(time);
+        // walls change only the vertical velocity, so the ball gets to x in `time` anyway
+        probe.update_different(dt);
+        time -= dt;
+    }
+    Some(probe.get_position().y)
+}
+
+// Keeps going for a point it picks anew every `reaction` seconds, missing it a bit.
+struct Aim {
+    settings: AiSettings,
+    target: Option<f32>,
+    // seconds until the AI looks at the ball again
+    next_look: f32,
+    rng: StdRng,
+}
+
+impl Aim {
+    fn new(settings: AiSettings, seed: u64) -> Self {
+        Self {
+            settings,
+            target: None,
+            next_look: 0.0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // `look` gives the point worth going for right now
+    fn keys(&mut self, situation: &Situation, look: impl FnOnce() -> f32) -> (bool, bool) {
+        self.next_look -= situation.dt;
+        if self.next_look <= 0.0 {
+            self.next_look += self.settings.reaction;
+            let error = self.settings.aim_error;
+            let miss = if error > 0.0 {
+                self.rng.gen_range(-error..=error)
+            } else {
+                0.0
+            };
+            self.target = Some(look() + miss);
+        }
+        // half a step is as close as the paddle can get, any less and it would shake
+        let tolerance = self
+            .settings
+            .dead_zone
+            .max(situation.paddle_speed * situation.dt / 2.0);
+        let difference = self
+            .target
+            .map_or(0.0, |target| target - situation.paddle.y);
+        // y grows downwards
+        (difference < -tolerance, difference > tolerance)
+    }
+}
+
+pub struct TrackingAi {
+    aim: Aim,
+}
+
+impl TrackingAi {
+    pub fn new(difficulty: Difficulty, seed: u64) -> Self {
+        Self {
+            aim: Aim::new(difficulty.settings(), seed),
+        }
+    }
+}
+
+impl Controller for TrackingAi {
+    fn keys(&mut self, situation: &Situation) -> (bool, bool) {
+        let ball = situation.ball.get_position().y;
+        self.aim.keys(situation, || ball)
+    }
+}
+
+pub struct PredictiveAi {
+    aim: Aim,
+}
+
+impl PredictiveAi {
+    pub fn new(difficulty: Difficulty, seed: u64) -> Self {
+        Self {
+            aim: Aim::new(difficulty.settings(), seed),
+        }
+    }
+}
+
+impl Controller for PredictiveAi {
+    // waits in the middle while the ball goes away
+    fn keys(&mut self, situation: &Situation) -> (bool, bool) {
+        let ball = situation.ball;
+        let (_, top, _, bottom) = ball.bounding_area;
+        let x = situation.paddle.x;
+        self.aim.keys(situation, || {
+            forecast_crossing(ball, x, situation.dt).unwrap_or((top + bottom) / 2.0)
+        })
+    }
+}
+
+// keys pressed by a person at this keyboard
+#[cfg(feature = "graphics")]
+pub struct KeyboardController {
+    up_keys: Vec<KeyCode>,
+    down_keys: Vec<KeyCode>,
+    pressed: Vec<KeyCode>,
+}
+
+#[cfg(feature = "graphics")]
+impl KeyboardController {
+    pub fn new(up_keys: &[KeyCode], down_keys: &[KeyCode]) -> Self {
+        Self {
+            up_keys: up_keys.to_vec(),
+            down_keys: down_keys.to_vec(),
+            pressed: Vec::new(),
+        }
+    }
+
+    // w/s on the left, arrows on the right, or both if nobody else plays
+    pub fn for_side(side: Side, alone: bool) -> Self {
+        match (side, alone) {
+            (_, true) => Self::new(&[KeyCode::W, KeyCode::Up], &[KeyCode::S, KeyCode::Down]),
+            (Side::Left, false) => Self::new(&[KeyCode::W], &[KeyCode::S]),
+            (Side::Right, false) => Self::new(&[KeyCode::Up], &[KeyCode::Down]),
+        }
+    }
+
+    fn held(&self, keys: &[KeyCode]) -> bool {
+        self.pressed.iter().any(|key| keys.contains(key))
+    }
+}
+
+#[cfg(feature = "graphics")]
+impl Controller for KeyboardController {
+    fn keys(&mut self, _situation: &Situation) -> (bool, bool) {
+        (self.held(&self.up_keys), self.held(&self.down_keys))
+    }
+
+    fn key_event(&mut self, key: KeyCode, pressed: bool) {
+        self.pressed.retain(|&k| k != key);
+        if pressed {
+            self.pressed.push(key);
+        }
+    }
+}
+
+// Controllers of both sides of the local game, as (kind, difficulty) of the left and right one.
+// AIs get random seeds, so that no two games are the same.
+#[cfg(feature = "graphics")]
+pub fn local_controllers(
+    left: (ControllerKind, Difficulty),
+    right: (ControllerKind, Difficulty),
+) -> (Box<dyn Controller>, Box<dyn Controller>) {
+    let alone = (left.0 == ControllerKind::Human) != (right.0 == ControllerKind::Human);
+    let make = |(kind, difficulty), side| -> Box<dyn Controller> {
+        match kind {
+            ControllerKind::Human => Box::new(KeyboardController::for_side(side, alone)),
+            ControllerKind::Tracking => Box::new(TrackingAi::new(difficulty, rand::random())),
+            ControllerKind::Predictive => Box::new(PredictiveAi::new(difficulty, rand::random())),
+        }
+    };
+    (make(left, Side::Left), make(right, Side::Right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: f32 = 1.0 / 60.0;
+
+    // radius 10 on the 800x600 field, so the centre stays between y 10 and 590
+    fn ball(velocity: (f32, f32)) -> BallAbstract {
+        BallAbstract::new(
+            400.0,
+            300.0,
+            10.0,
+            velocity.into(),
+            (0.0, 0.0, 800.0, 600.0),
+        )
+    }
+
+    fn assert_crossing(velocity: (f32, f32), x: f32, expected: f32) {
+        let y = forecast_crossing(&ball(velocity), x, STEP).expect("ball gets there");
+        assert!(
+            (y - expected).abs() < 0.01,
+            "{:?} crosses at {}, not {}",
+            velocity,
+            y,
+            expected
+        );
+    }
+
+    #[test]
+    fn crossing_without_walls() {
+        assert_crossing((-200.0, 0.0), 100.0, 300.0);
+        assert_crossing((-200.0, 100.0), 100.0, 450.0);
+        assert_crossing((300.0, -100.0), 700.0, 200.0);
+    }
+
+    #[test]
+    fn crossing_after_bounces() {
+        // 1.5 seconds to get there, 750 without the wall is 160 past 590
+        assert_crossing((-200.0, 300.0), 100.0, 430.0);
+        // 1500, 910 past the bottom: off it to -320, then off the top to 340
+        assert_crossing((-200.0, 800.0), 100.0, 340.0);
+        // steps which don't divide the time
+        let y = forecast_crossing(&ball((-200.0, 300.0)), 100.0, 0.4).unwrap();
+        assert!((y - 430.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn no_crossing_when_moving_away() {
+        assert_eq!(forecast_crossing(&ball((200.0, 50.0)), 100.0, STEP), None);
+        assert_eq!(forecast_crossing(&ball((0.0, 50.0)), 100.0, STEP), None);
+        assert_eq!(forecast_crossing(&ball((-200.0, 0.0)), 400.0, STEP), None);
+    }
+}
@@ -0,0 +1,182 @@
+// Maths that gives bit-identical results on every platform, used by Physics::Deterministic.
+//
+// Rust's f32 +, -, *, / and sqrt are IEEE 754 operations, correctly rounded, so every
+// platform computes the same bits, as long as the compiler neither fuses nor reorders them
+// (rustc doesn't) and the target does f32 arithmetic in f32 (true everywhere but 32-bit x86
+// without SSE2, which rounds through x87 registers).
+// sin and cos on the other hand come from the platform's maths library and may differ in
+// the last bit, which is enough for two simulations of the same inputs to drift apart after
+// a few bounces. So here they are built from the basic operations only, in a fixed order.
+
+use std::f32::consts::FRAC_2_PI;
+
+// PI/2 in three parts, each with few enough bits that quadrant * part is exact for the
+// quadrants of |x| <= MAX_ACCURATE, so the reduction loses nothing to PI/2 not being an f32
+const PI_2_PARTS: [f32; 3] = [1.570_312_5, 4.837_513e-4, 7.549_79e-8];
+// beyond it the result is still the same everywhere, just further from the exact values
+pub const MAX_ACCURATE: f32 = 8192.0;
+
+// (sin x, cos x), within 2e-7 of the exact values for |x| up to MAX_ACCURATE
+pub fn sin_cos(x: f32) -> (f32, f32) {
+    // reduced to r in [-PI/4, PI/4], x = r + quadrant * PI/2
+    let quadrant = (x * FRAC_2_PI).round();
+    let r = x - quadrant * PI_2_PARTS[0] - quadrant * PI_2_PARTS[1] - quadrant * PI_2_PARTS[2];
+    let r2 = r * r;
+    // Taylor series, the first left out terms are below 3e-8 on [-PI/4, PI/4]
+    let sin = r
+        * (1.0
+            + r2 * (-1.0 / 6.0
+                + r2 * (1.0 / 120.0 + r2 * (-1.0 / 5040.0 + r2 * (1.0 / 362880.0)))));
+    let cos =
+        1.0 + r2 * (-1.0 / 2.0 + r2 * (1.0 / 24.0 + r2 * (-1.0 / 720.0 + r2 * (1.0 / 40320.0))));
+    match (quadrant as i64).rem_euclid(4) {
+        0 => (sin, cos),
+        1 => (cos, -sin),
+        2 => (-sin, -cos),
+        _ => (-cos, sin),
+    }
+}
+
+// same as glam::Vec2::from_angle
+pub fn from_angle(angle: f32) -> glam::Vec2 {
+    let (sin, cos) = sin_cos(angle);
+    glam::vec2(cos, sin)
+}
+
+// same as rotation.rotate(v), written out so that the order of operations is fixed here
+pub fn rotate(v: glam::Vec2, rotation: glam::Vec2) -> glam::Vec2 {
+    glam::vec2(
+        rotation.x * v.x - rotation.y * v.y,
+        rotation.y * v.x + rotation.x * v.y,
+    )
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// 64-bit FNV-1a over the little endian bytes of the values written, unlike
+// std::hash::DefaultHasher it's the same in every run, build and platform.
+// f32 are hashed by their bits, so 0.0 and -0.0 differ and NaNs hash as they are.
+pub struct StateHasher {
+    hash: u64,
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self {
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl StateHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash ^= byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn write_u32(&mut self, x: u32) {
+        self.write_bytes(&x.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, x: f32) {
+        self.write_bytes(&x.to_bits().to_le_bytes());
+    }
+
+    pub fn write_vec2(&mut self, v: glam::Vec2) {
+        self.write_f32(v.x);
+        self.write_f32(v.y);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_4;
+
+    fn assert_close(x: f32) {
+        let (sin, cos) = sin_cos(x);
+        let (std_sin, std_cos) = x.sin_cos();
+        assert!(
+            (sin - std_sin).abs() <= 2e-7 && (cos - std_cos).abs() <= 2e-7,
+            "sin_cos({}) = ({}, {}), not ({}, {})",
+            x,
+            sin,
+            cos,
+            std_sin,
+            std_cos
+        );
+    }
+
+    // every step-th value in [-max, max]
+    fn assert_close_up_to(max: f32, step: f32) {
+        let mut x = -max;
+        while x <= max {
+            assert_close(x);
+            x += step;
+        }
+    }
+
+    #[test]
+    fn sin_cos_is_close_for_bounce_angles() {
+        assert_close_up_to(FRAC_PI_4, 1e-4);
+        assert_eq!(sin_cos(0.0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn sin_cos_is_close_up_to_max_accurate() {
+        assert_close_up_to(MAX_ACCURATE, 0.0137);
+        // the far quadrants, where PI/2 as a single f32 would be 1e-4 off
+        for x in [MAX_ACCURATE, -MAX_ACCURATE, 2999.9, -3000.0] {
+            assert_close(x);
+        }
+    }
+
+    // Physics::Deterministic bounces go through these, replays depend on every bit
+    #[test]
+    fn sin_cos_gives_the_same_bits() {
+        let bits = |x: f32| {
+            let (sin, cos) = sin_cos(x);
+            (sin.to_bits(), cos.to_bits())
+        };
+        assert_eq!(bits(0.5), (0x3ef5_7744, 0x3f60_a940));
+        assert_eq!(bits(-0.7), (0xbf24_eb73, 0x3f43_ccb3));
+        assert_eq!(bits(FRAC_PI_4), (0x3f35_04f4, 0x3f35_04f3));
+    }
+
+    #[test]
+    fn rotation_is_the_same_as_glam() {
+        let v = glam::vec2(-350.0, 120.0);
+        for angle in [-0.6, -0.1, 0.0, 0.3, 0.78] {
+            let expected = glam::Vec2::from_angle(angle).rotate(v);
+            assert!((rotate(v, from_angle(angle)) - expected).length() < 1e-4);
+        }
+    }
+
+    // replays keep these hashes, they must not change
+    #[test]
+    fn state_hash_stays_the_same() {
+        assert_eq!(StateHasher::new().finish(), FNV_OFFSET_BASIS);
+        let mut hasher = StateHasher::new();
+        hasher.write_bytes(b"a");
+        // the published FNV-1a test vector
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let mut hasher = StateHasher::new();
+        hasher.write_u32(7);
+        hasher.write_f32(1.5);
+        hasher.write_f32(-0.0);
+        hasher.write_vec2(glam::vec2(400.0, -300.25));
+        assert_eq!(hasher.finish(), 0x272f_5f1c_0429_5999);
+    }
+}
@@ -1,6 +1,12 @@
 pub mod ball;
 pub mod configuration;
+pub mod controller;
+pub mod deterministic;
 pub mod paddle;
 pub mod paddle_like;
+#[cfg(feature = "graphics")]
 pub mod pong;
+#[cfg(feature = "graphics")]
+pub mod render;
+pub mod rules;
 pub mod state;
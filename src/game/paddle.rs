@@ -1,8 +1,10 @@
 use super::paddle_like::PaddleLike;
-use super::{ball::BallAbstract, configuration::FromConfiguration};
-use ggez::{glam, graphics};
+use super::{
+    ball::BallAbstract,
+    configuration::{Color, FromConfiguration},
+};
 
-const _HIT_COLOR: graphics::Color = graphics::Color::GREEN;
+const _HIT_COLOR: Color = Color::GREEN;
 
 pub struct Paddle<E: PaddleLike> {
     pub position: glam::Vec2,
@@ -10,7 +12,7 @@ pub struct Paddle<E: PaddleLike> {
     pub bounding_area: (f32, f32),
     pub speed: f32,
     pub velocity: f32,
-    color: graphics::Color,
+    color: Color,
     paddle_like: E,
 }
 
@@ -18,7 +20,7 @@ impl<E: PaddleLike> Paddle<E> {
     pub fn new(
         x: f32,
         y: f32,
-        color: graphics::Color,
+        color: Color,
         paddle_like: E,
         height: f32,
         bounding_area: (f32, f32),
@@ -39,6 +41,14 @@ impl<E: PaddleLike> Paddle<E> {
         self.paddle_like.bouncing(self, ball)
     }
 
+    pub fn time_of_impact(&self, ball: &BallAbstract, dt: f32) -> Option<(f32, glam::Vec2)> {
+        self.paddle_like.time_of_impact(self, ball, dt)
+    }
+
+    pub fn get_paddle_like(&self) -> &E {
+        &self.paddle_like
+    }
+
     pub fn set_position(&mut self, position: impl Into<glam::Vec2>) {
         self.position = position.into();
     }
@@ -51,14 +61,10 @@ impl<E: PaddleLike> Paddle<E> {
         self.velocity
     }
 
-    pub fn get_color(&self) -> graphics::Color {
+    pub fn get_color(&self) -> Color {
         self.color
     }
 
-    pub fn draw(&self, canvas: &mut graphics::Canvas) {
-        self.paddle_like.draw(self, canvas);
-    }
-
     pub fn reset(&mut self) {
         self.position = glam::vec2(self.position.x, self.bounding_area.1 / 2.0);
     }
@@ -1,27 +1,102 @@
 use super::ball::BallAbstract;
 use super::configuration::Configuration;
 use super::configuration::FromConfiguration;
+use super::configuration::Physics;
+use super::deterministic;
 use super::paddle::Paddle;
-use ggez::{glam, graphics};
+
+// number of bisection steps used by the generic time of impact search
+const TIME_OF_IMPACT_ITERATIONS: u32 = 12;
 
 pub trait PaddleLike {
-    fn draw(&self, paddle: &Paddle<impl PaddleLike>, canvas: &mut graphics::Canvas);
     fn bouncing(&self, paddle: &Paddle<impl PaddleLike>, ball: &BallAbstract)
         -> Option<glam::Vec2>;
+
+    // Earliest time in [0, dt] at which the ball, moving with its current velocity, hits
+    // the paddle, together with the velocity it bounces off with.
+    // Default implementation samples `bouncing` along the path often enough that the ball
+    // can't skip over the paddle, and then bisects the first hit,
+    // so that every PaddleLike gets continuous collisions for free.
+    fn time_of_impact(
+        &self,
+        paddle: &Paddle<impl PaddleLike>,
+        ball: &BallAbstract,
+        dt: f32,
+    ) -> Option<(f32, glam::Vec2)> {
+        let start = ball.get_position();
+        let vel = ball.get_velocity();
+        // only hits when moving towards the paddle count, otherwise ball bouncing off
+        // would get caught again in the next step
+        if (paddle.get_position().x - start.x) * vel.x <= 0.0 {
+            return None;
+        }
+        let mut probe = ball.clone();
+        let mut hit_at = |t: f32| {
+            probe.set_position(start + t * vel);
+            self.bouncing(paddle, &probe)
+        };
+
+        let distance = vel.length() * dt;
+        let samples = (2.0 * distance / ball.get_radius()).ceil().max(1.0) as u32;
+        let mut prev_t = 0.0;
+        for i in 0..=samples {
+            let t = dt * i as f32 / samples as f32;
+            if let Some(mut new_vel) = hit_at(t) {
+                let (mut lo, mut hi) = (prev_t, t);
+                if i > 0 {
+                    for _ in 0..TIME_OF_IMPACT_ITERATIONS {
+                        let mid = (lo + hi) / 2.0;
+                        match hit_at(mid) {
+                            Some(v) => {
+                                hi = mid;
+                                new_vel = v;
+                            }
+                            None => lo = mid,
+                        }
+                    }
+                }
+                return Some((hi, new_vel));
+            }
+            prev_t = t;
+        }
+        None
+    }
 }
 
 pub struct RectangularPaddle {
-    half_height: f32,
-    half_width: f32,
+    pub(crate) half_height: f32,
+    pub(crate) half_width: f32,
     paddle_to_ball_speedup: f32,
+    physics: Physics,
 }
 
 impl RectangularPaddle {
-    pub fn new(half_height: f32, half_width: f32, paddle_to_ball_speedup: f32) -> Self {
+    pub fn new(
+        half_height: f32,
+        half_width: f32,
+        paddle_to_ball_speedup: f32,
+        physics: Physics,
+    ) -> Self {
         Self {
             half_height,
             half_width,
             paddle_to_ball_speedup,
+            physics,
+        }
+    }
+
+    // velocity of the ball after hitting the paddle y_hit away from its centre
+    fn bounce_velocity(&self, paddle_vel: f32, mut ball_vel: glam::Vec2, y_hit: f32) -> glam::Vec2 {
+        ball_vel.x = -ball_vel.x;
+        ball_vel += glam::Vec2::splat(paddle_vel * paddle_vel.signum() * ball_vel.y.signum())
+            * self.paddle_to_ball_speedup;
+        let frac = (ball_vel.x.signum() * y_hit / self.half_height).clamp(-1.0, 1.0);
+        let angle = std::f32::consts::FRAC_PI_4 * frac;
+        match self.physics {
+            Physics::Native => glam::Vec2::from_angle(angle).rotate(ball_vel),
+            Physics::Deterministic => {
+                deterministic::rotate(ball_vel, deterministic::from_angle(angle))
+            }
         }
     }
 }
@@ -32,21 +107,63 @@ impl FromConfiguration for RectangularPaddle {
             config.paddle_height / 2.0,
             config.paddle_width / 2.0,
             config.paddle_to_ball_speedup,
+            config.physics,
         )
     }
 }
 
-impl PaddleLike for RectangularPaddle {
-    fn draw(&self, paddle: &Paddle<impl PaddleLike>, canvas: &mut graphics::Canvas) {
-        canvas.draw(
-            &graphics::Quad,
-            graphics::DrawParam::new()
-                .dest(paddle.get_position() - glam::vec2(self.half_width, self.half_height))
-                .scale(2.0 * glam::vec2(self.half_width, self.half_height))
-                .color(paddle.get_color()),
-        );
+// Earliest t in [0, 1] at which a point moving from `start` by `delta` gets within `radius`
+// of the circle centre.
+fn ray_circle(
+    start: glam::Vec2,
+    delta: glam::Vec2,
+    centre: glam::Vec2,
+    radius: f32,
+) -> Option<f32> {
+    let m = start - centre;
+    let c = m.length_squared() - radius * radius;
+    if c <= 0.0 {
+        return Some(0.0);
     }
+    let a = delta.length_squared();
+    let b = m.dot(delta);
+    if a == 0.0 || b >= 0.0 {
+        return None;
+    }
+    let disc = b * b - a * c;
+    if disc < 0.0 {
+        return None;
+    }
+    let t = (-b - disc.sqrt()) / a;
+    (t <= 1.0).then_some(t)
+}
+
+// Earliest t in [0, 1] at which a point moving from `start` by `delta` enters the box
+// centred at the origin with the given half extents.
+fn ray_box(start: glam::Vec2, delta: glam::Vec2, half_extents: glam::Vec2) -> Option<f32> {
+    let mut t_enter = f32::NEG_INFINITY;
+    let mut t_exit = f32::INFINITY;
+    for (p, d, e) in [
+        (start.x, delta.x, half_extents.x),
+        (start.y, delta.y, half_extents.y),
+    ] {
+        if d == 0.0 {
+            if p.abs() > e {
+                return None;
+            }
+        } else {
+            let (t1, t2) = ((-e - p) / d, (e - p) / d);
+            t_enter = t_enter.max(t1.min(t2));
+            t_exit = t_exit.min(t1.max(t2));
+        }
+    }
+    if t_enter > t_exit || t_exit < 0.0 || t_enter > 1.0 {
+        return None;
+    }
+    Some(t_enter.max(0.0))
+}
 
+impl PaddleLike for RectangularPaddle {
     fn bouncing(
         &self,
         paddle: &Paddle<impl PaddleLike>,
@@ -54,19 +171,148 @@ impl PaddleLike for RectangularPaddle {
     ) -> Option<glam::Vec2> {
         let ball_pos = ball.get_position();
         let r = ball.get_radius();
-        let mut ball_vel = ball.get_velocity();
         let my_pos = paddle.get_position();
-        let my_vel = paddle.get_velocity();
         let y_hit = ball_pos.y - my_pos.y;
         if (ball_pos.x - my_pos.x).abs() < r + self.half_width && y_hit.abs() < self.half_height {
-            ball_vel.x = -ball_vel.x;
-            ball_vel += glam::Vec2::splat(my_vel * my_vel.signum() * ball_vel.y.signum())
-                * self.paddle_to_ball_speedup;
-            let frac = ball_vel.x.signum() * y_hit / self.half_height;
-            ball_vel = glam::Vec2::from_angle(std::f32::consts::FRAC_PI_4 * frac).rotate(ball_vel);
-            // println!("ball vel {}", ball_vel);
-            return Some(ball_vel);
+            return Some(self.bounce_velocity(paddle.get_velocity(), ball.get_velocity(), y_hit));
         }
         None
     }
+
+    // Swept circle against the rectangle: the ball centre is traced as a ray against
+    // the rectangle grown by the ball radius, with rounded corners.
+    fn time_of_impact(
+        &self,
+        paddle: &Paddle<impl PaddleLike>,
+        ball: &BallAbstract,
+        dt: f32,
+    ) -> Option<(f32, glam::Vec2)> {
+        let r = ball.get_radius();
+        let vel = ball.get_velocity();
+        let start = ball.get_position() - paddle.get_position();
+        if start.x * vel.x >= 0.0 {
+            return None;
+        }
+        let delta = vel * dt;
+        let half = glam::vec2(self.half_width, self.half_height);
+
+        let mut t = ray_box(start, delta, half + glam::Vec2::splat(r))?;
+        let contact = start + t * delta;
+        if contact.x.abs() > half.x && contact.y.abs() > half.y {
+            // in the corner region of the grown box, the real shape is a quarter circle
+            let corner = glam::vec2(half.x * contact.x.signum(), half.y * contact.y.signum());
+            t = ray_circle(start, delta, corner, r)?;
+        }
+        let contact = start + t * delta;
+        Some((
+            t * dt,
+            self.bounce_velocity(paddle.get_velocity(), vel, contact.y),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Color;
+
+    const SPEED: f32 = 60_000.0;
+    const DT: f32 = 1.0 / 60.0;
+
+    // PaddleLike with only `bouncing`, so it gets the sampled time_of_impact
+    struct Sampled(RectangularPaddle);
+
+    impl PaddleLike for Sampled {
+        fn bouncing(
+            &self,
+            paddle: &Paddle<impl PaddleLike>,
+            ball: &BallAbstract,
+        ) -> Option<glam::Vec2> {
+            self.0.bouncing(paddle, ball)
+        }
+    }
+
+    fn rectangle() -> RectangularPaddle {
+        RectangularPaddle::new(40.0, 5.0, 0.0, Physics::Native)
+    }
+
+    // 80 high and 10 wide, its centre at (100, 300)
+    fn paddle<E: PaddleLike>(paddle_like: E) -> Paddle<E> {
+        Paddle::new(
+            100.0,
+            300.0,
+            Color::WHITE,
+            paddle_like,
+            80.0,
+            (0.0, 600.0),
+            300.0,
+        )
+    }
+
+    // radius 5, at x 400 flying left, it would be at x -600 after DT
+    fn ball(y: f32) -> BallAbstract {
+        BallAbstract::new(
+            400.0,
+            y,
+            5.0,
+            glam::vec2(-SPEED, 0.0),
+            (0.0, 0.0, 800.0, 600.0),
+        )
+    }
+
+    fn assert_hit_at(hit: Option<(f32, glam::Vec2)>, x: f32) -> glam::Vec2 {
+        let (t, velocity) = hit.expect("ball hits the paddle");
+        let expected = (400.0 - x) / SPEED;
+        assert!(
+            (t - expected).abs() < 1e-6,
+            "hit at {}, not {}",
+            t,
+            expected
+        );
+        assert!(velocity.x > 0.0, "bounces back");
+        velocity
+    }
+
+    #[test]
+    fn fast_ball_doesnt_pass_through() {
+        let ball = ball(300.0);
+        // neither before nor after the step the ball touches the paddle
+        let mut after = ball.clone();
+        after.set_position(ball.get_position() + DT * ball.get_velocity());
+        assert!(paddle(rectangle()).bouncing(&after).is_none());
+
+        // it touches the face at x 100 + 5 + 5
+        assert_hit_at(paddle(rectangle()).time_of_impact(&ball, DT), 110.0);
+        assert_hit_at(
+            paddle(Sampled(rectangle())).time_of_impact(&ball, DT),
+            110.0,
+        );
+    }
+
+    #[test]
+    fn ball_hits_the_edge() {
+        // the ball's centre passes 3 above the top, it touches the corner when it is 4 away
+        let velocity = assert_hit_at(paddle(rectangle()).time_of_impact(&ball(257.0), DT), 109.0);
+        // from the far end of the paddle, it goes back upwards
+        assert!(velocity.y < 0.0);
+        assert!((velocity.length() - SPEED).abs() < 1.0);
+
+        // 6 above misses it
+        assert!(paddle(rectangle())
+            .time_of_impact(&ball(254.0), DT)
+            .is_none());
+        assert!(paddle(Sampled(rectangle()))
+            .time_of_impact(&ball(254.0), DT)
+            .is_none());
+    }
+
+    #[test]
+    fn ball_moving_away_isnt_caught() {
+        let mut ball = ball(300.0);
+        ball.set_velocity(glam::vec2(SPEED, 0.0));
+        assert!(paddle(rectangle()).time_of_impact(&ball, DT).is_none());
+        assert!(paddle(Sampled(rectangle()))
+            .time_of_impact(&ball, DT)
+            .is_none());
+    }
 }
@@ -1,17 +1,180 @@
-use super::{configuration::Configuration, paddle_like::RectangularPaddle};
-use ggez::{event, GameResult};
+use super::controller::{Controller, Situation};
+use super::paddle::paddle_from_configuration;
+use super::render::{Ball, PaddleDraw};
+use super::rules::{Match, Side};
+use super::state::{game_frame, Input, RoundResult};
+use super::{
+    configuration::{Configuration, FromConfiguration},
+    paddle::Paddle,
+    paddle_like::RectangularPaddle,
+};
+use ggez::{event, graphics, Context, GameError, GameResult};
 
-use super::state::State;
+const DESIRED_FPS: u32 = 60;
 
-pub fn pong() -> GameResult {
-    let config = Configuration::default();
+const SCREEN_COLOR: graphics::Color = graphics::Color::BLACK;
+const HUD_COLOR: graphics::Color = graphics::Color::WHITE;
+const HUD_MARGIN: f32 = 10.0;
+
+pub struct State<L: PaddleDraw, R: PaddleDraw> {
+    paddle_left: Paddle<L>,
+    paddle_right: Paddle<R>,
+    left: Box<dyn Controller>,
+    right: Box<dyn Controller>,
+    ball: Ball,
+    game: Match,
+    screen: graphics::Rect,
+}
+
+impl<L: PaddleDraw + FromConfiguration, R: PaddleDraw + FromConfiguration> State<L, R> {
+    pub fn new(
+        config: Configuration,
+        ctx: &mut Context,
+        left: Box<dyn Controller>,
+        right: Box<dyn Controller>,
+    ) -> Self {
+        let (paddle_left, paddle_right) = paddle_from_configuration(&config);
+        let ball = Ball::from_configuration(&config, ctx);
+        let game = Match::new(config.rules, ball.ball_abstract.first_serve());
+        Self {
+            paddle_left,
+            paddle_right,
+            ball,
+            left,
+            right,
+            game,
+            screen: graphics::Rect::new(0.0, 0.0, config.screen_width, config.screen_height),
+        }
+    }
+}
+
+impl<L: PaddleDraw, R: PaddleDraw> ggez::event::EventHandler<GameError> for State<L, R> {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        // let dt :f32 = ctx.time.delta();
+        let dt = 1.0 / DESIRED_FPS as f32;
+        // let mut num_of_updates = 0;
+        while ctx.time.check_update_time(DESIRED_FPS) {
+            // println!("dt: {}", dt);
+            let ball = &self.ball.ball_abstract;
+            let (left_up, left_down) = self.left.keys(&Situation::new(ball, &self.paddle_left, dt));
+            let (right_up, right_down) =
+                self.right
+                    .keys(&Situation::new(ball, &self.paddle_right, dt));
+            let input = Input::new(left_up, left_down, right_up, right_down);
+            match game_frame(
+                &mut self.ball.ball_abstract,
+                &mut self.paddle_left,
+                &mut self.paddle_right,
+                dt,
+                &input,
+                &mut self.game,
+            ) {
+                RoundResult::LeftScored => {
+                    println!(
+                        "Left scored! left: {}, right: {}",
+                        self.game.score.0, self.game.score.1
+                    );
+                }
+                RoundResult::RightScored => {
+                    println!(
+                        "Right scored! left: {}, right: {}",
+                        self.game.score.0, self.game.score.1
+                    );
+                }
+                RoundResult::None => continue,
+            }
+            if let Some(winner) = self.game.winner() {
+                println!("{:?} won the match!", winner);
+            }
+            // num_of_updates += 1;
+            // if num_of_updates > 1 {
+            //    println!("num of updates: {}", num_of_updates);
+            //}
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let mut canvas = graphics::Canvas::from_frame(ctx, SCREEN_COLOR);
+        canvas.set_screen_coordinates(self.screen);
+
+        self.ball.draw(&mut canvas);
+        self.paddle_left.draw(&mut canvas);
+        self.paddle_right.draw(&mut canvas);
+
+        let (left, right) = self.game.score;
+        let hud = graphics::Text::new(format!("Left {} : {} Right", left, right));
+        let hud_width = hud.measure(ctx)?.x;
+        canvas.draw(
+            &hud,
+            graphics::DrawParam::new()
+                .dest(glam::vec2((self.screen.w - hud_width) / 2.0, HUD_MARGIN))
+                .color(HUD_COLOR),
+        );
+        if let Some(winner) = self.game.winner() {
+            let winner = match winner {
+                Side::Left => "Left",
+                Side::Right => "Right",
+            };
+            let text = graphics::Text::new(format!("{} won {} : {}", winner, left, right));
+            let size = text.measure(ctx)?;
+            canvas.draw(
+                &text,
+                graphics::DrawParam::new()
+                    .dest(glam::vec2(
+                        (self.screen.w - size.x) / 2.0,
+                        (self.screen.h - size.y) / 2.0,
+                    ))
+                    .color(HUD_COLOR),
+            );
+        }
+
+        canvas.finish(ctx)?;
+        ggez::timer::yield_now();
+        Ok(())
+    }
+
+    fn key_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        keyinput: ggez::input::keyboard::KeyInput,
+        _repeat: bool,
+    ) -> GameResult {
+        // println!("key pressed: {:?}", keyinput.keycode);
+        if let Some(key) = keyinput.keycode {
+            self.left.key_event(key, true);
+            self.right.key_event(key, true);
+        }
+        Ok(())
+    }
+
+    fn key_up_event(
+        &mut self,
+        _ctx: &mut Context,
+        keyinput: ggez::input::keyboard::KeyInput,
+    ) -> GameResult {
+        // println!("key released: {:?}", keyinput.keycode);
+        if let Some(key) = keyinput.keycode {
+            self.left.key_event(key, false);
+            self.right.key_event(key, false);
+        }
+        Ok(())
+    }
+}
+
+// each paddle is moved by its controller, keyboard or AI
+pub fn pong(
+    config: Configuration,
+    left: Box<dyn Controller>,
+    right: Box<dyn Controller>,
+) -> GameResult {
     let screen_width = config.screen_width;
     let screen_height = config.screen_height;
     let (mut ctx, event_loop) = ggez::ContextBuilder::new("pong", "marcin g")
         .window_setup(ggez::conf::WindowSetup::default().title("Pong"))
         .window_mode(ggez::conf::WindowMode::default().dimensions(screen_width, screen_height))
         .build()?;
-    let state = State::<RectangularPaddle, RectangularPaddle>::new(config, &mut ctx);
+    let state = State::<RectangularPaddle, RectangularPaddle>::new(config, &mut ctx, left, right);
     // let mut c = conf::Conf::new();
     // c.window_mode(ggez::conf::WindowMode::default().dimensions(800.0, 600.0));
     event::run(ctx, event_loop, state);
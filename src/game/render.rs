@@ -0,0 +1,142 @@
+use ggez::graphics::{self, Canvas};
+
+use super::ball::BallAbstract;
+use super::configuration::{Color, Configuration};
+use super::paddle::Paddle;
+use super::paddle_like::{PaddleLike, RectangularPaddle};
+use super::rules::Side;
+
+impl From<Color> for graphics::Color {
+    fn from(color: Color) -> Self {
+        graphics::Color::new(color.r, color.g, color.b, color.a)
+    }
+}
+
+// width of the border drawn around highlighted paddles
+const HIGHLIGHT_MARGIN: f32 = 3.0;
+
+// drawing is kept apart from PaddleLike, so that the simulation builds without ggez
+pub trait PaddleDraw: PaddleLike {
+    fn draw(&self, paddle: &Paddle<impl PaddleLike>, canvas: &mut graphics::Canvas);
+    // marks the paddle e.g. as the one controlled by the player, drawn under the paddle
+    fn draw_highlight(
+        &self,
+        paddle: &Paddle<impl PaddleLike>,
+        canvas: &mut graphics::Canvas,
+        color: graphics::Color,
+    );
+}
+
+impl PaddleDraw for RectangularPaddle {
+    fn draw(&self, paddle: &Paddle<impl PaddleLike>, canvas: &mut graphics::Canvas) {
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest(paddle.get_position() - glam::vec2(self.half_width, self.half_height))
+                .scale(2.0 * glam::vec2(self.half_width, self.half_height))
+                .color(paddle.get_color()),
+        );
+    }
+
+    fn draw_highlight(
+        &self,
+        paddle: &Paddle<impl PaddleLike>,
+        canvas: &mut graphics::Canvas,
+        color: graphics::Color,
+    ) {
+        let half = glam::vec2(self.half_width, self.half_height) + HIGHLIGHT_MARGIN;
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest(paddle.get_position() - half)
+                .scale(2.0 * half)
+                .color(color),
+        );
+    }
+}
+
+impl<E: PaddleDraw> Paddle<E> {
+    pub fn draw(&self, canvas: &mut graphics::Canvas) {
+        self.get_paddle_like().draw(self, canvas);
+    }
+
+    pub fn draw_highlighted(&self, canvas: &mut graphics::Canvas, color: graphics::Color) {
+        self.get_paddle_like().draw_highlight(self, canvas, color);
+        self.draw(canvas);
+    }
+}
+
+pub struct Ball {
+    pub ball_abstract: BallAbstract,
+    ball_mesh: graphics::Mesh,
+}
+
+impl Ball {
+    pub fn new(
+        x: f32,
+        y: f32,
+        radius: f32,
+        initial_velocity: glam::Vec2,
+        ball_color: Color,
+        bounding_area: (f32, f32, f32, f32),
+        ctx: &ggez::Context,
+    ) -> Self {
+        Self {
+            ball_abstract: BallAbstract::new(x, y, radius, initial_velocity, bounding_area),
+            ball_mesh: graphics::Mesh::new_circle(
+                ctx,
+                ggez::graphics::DrawMode::fill(),
+                glam::vec2(0.0, 0.0),
+                radius,
+                0.1,
+                ball_color.into(),
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn reset(&mut self, towards: Side) {
+        self.ball_abstract.reset(towards)
+    }
+
+    pub fn get_position(&self) -> glam::Vec2 {
+        self.ball_abstract.get_position()
+    }
+
+    pub fn get_radius(&self) -> f32 {
+        self.ball_abstract.get_radius()
+    }
+
+    pub fn get_velocity(&self) -> glam::Vec2 {
+        self.ball_abstract.get_velocity()
+    }
+
+    pub fn set_velocity(&mut self, velocity: glam::Vec2) {
+        self.ball_abstract.set_velocity(velocity);
+    }
+
+    pub fn set_position(&mut self, position: impl Into<glam::Vec2>) {
+        self.ball_abstract.set_position(position);
+    }
+
+    // costlier but more accurate version of update
+    pub fn update_different(&mut self, dt: f32) -> Option<bool> {
+        self.ball_abstract.update_different(dt)
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas) {
+        canvas.draw(&self.ball_mesh, self.ball_abstract.position);
+    }
+
+    pub fn from_configuration(config: &Configuration, ctx: &ggez::Context) -> Self {
+        Self::new(
+            config.screen_width / 2.0,
+            config.screen_height / 2.0,
+            config.ball_radius,
+            config.ball_initial_velocity,
+            config.ball_color,
+            (0.0, 0.0, config.screen_width, config.screen_height),
+            ctx,
+        )
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+
+const POINTS_TO_WIN: u32 = 11;
+const WIN_BY_TWO: bool = true;
+const SERVE_DELAY: f32 = 1.0;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        }
+    }
+}
+
+// where the ball goes after a point
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServeRule {
+    // every serve goes the other way than the previous one
+    #[default]
+    Alternate,
+    // towards the player who lost the point
+    ToConceder,
+}
+
+// how a match is played, part of the configuration, so in online mode the server's rules hold
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchRules {
+    pub points_to_win: u32,
+    // at points_to_win the lead must be at least two points, the match goes on until it is
+    pub win_by_two: bool,
+    pub serve: ServeRule,
+    // seconds the ball waits in the middle before every serve
+    pub serve_delay: f32,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            points_to_win: POINTS_TO_WIN,
+            win_by_two: WIN_BY_TWO,
+            serve: ServeRule::default(),
+            serve_delay: SERVE_DELAY,
+        }
+    }
+}
+
+impl MatchRules {
+    // side which has won with this (left, right) score, if any
+    pub fn winner(&self, score: (u32, u32)) -> Option<Side> {
+        let (leader, points, lead) = if score.0 >= score.1 {
+            (Side::Left, score.0, score.0 - score.1)
+        } else {
+            (Side::Right, score.1, score.1 - score.0)
+        };
+        let needed_lead = if self.win_by_two { 2 } else { 1 };
+        (points >= self.points_to_win && lead >= needed_lead).then_some(leader)
+    }
+}
+
+// Score of a match being played, and what happens with the next serve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub rules: MatchRules,
+    // (left, right)
+    pub score: (u32, u32),
+    // side the next (or current) serve goes towards
+    pub serve_towards: Side,
+    // seconds until the ball is served, it waits in the middle until then
+    pub serve_in: f32,
+}
+
+impl Match {
+    // first serve goes towards `first_serve`
+    pub fn new(rules: MatchRules, first_serve: Side) -> Self {
+        Self {
+            rules,
+            score: (0, 0),
+            serve_towards: first_serve,
+            serve_in: rules.serve_delay,
+        }
+    }
+
+    pub fn winner(&self) -> Option<Side> {
+        self.rules.winner(self.score)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.winner().is_some()
+    }
+
+    // counts the point and sets up the next serve
+    pub fn point(&mut self, scorer: Side) {
+        match scorer {
+            Side::Left => self.score.0 += 1,
+            Side::Right => self.score.1 += 1,
+        }
+        self.serve_towards = match self.rules.serve {
+            ServeRule::Alternate => self.serve_towards.opposite(),
+            ServeRule::ToConceder => scorer.opposite(),
+        };
+        self.serve_in = self.rules.serve_delay;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn played_to(points_to_win: u32, win_by_two: bool) -> MatchRules {
+        MatchRules {
+            points_to_win,
+            win_by_two,
+            ..MatchRules::default()
+        }
+    }
+
+    #[test]
+    fn winner_needs_the_points() {
+        let rules = played_to(11, true);
+        assert_eq!(rules.winner((0, 0)), None);
+        assert_eq!(rules.winner((10, 3)), None);
+        assert_eq!(rules.winner((11, 3)), Some(Side::Left));
+        assert_eq!(rules.winner((0, 11)), Some(Side::Right));
+    }
+
+    #[test]
+    fn win_by_two_goes_on_past_the_points() {
+        let rules = played_to(11, true);
+        assert_eq!(rules.winner((11, 10)), None);
+        assert_eq!(rules.winner((12, 11)), None);
+        assert_eq!(rules.winner((12, 12)), None);
+        assert_eq!(rules.winner((14, 12)), Some(Side::Left));
+        assert_eq!(rules.winner((10, 12)), Some(Side::Right));
+
+        let no_two = played_to(11, false);
+        assert_eq!(no_two.winner((11, 10)), Some(Side::Left));
+        assert_eq!(no_two.winner((10, 11)), Some(Side::Right));
+        assert_eq!(no_two.winner((10, 10)), None);
+    }
+
+    #[test]
+    fn match_counts_points_and_serves() {
+        let mut game = Match::new(played_to(2, true), Side::Left);
+        game.point(Side::Left);
+        assert_eq!(game.serve_towards, Side::Right);
+        game.point(Side::Right);
+        game.point(Side::Left);
+        assert_eq!((game.score, game.winner()), ((2, 1), None));
+        game.point(Side::Left);
+        assert_eq!((game.score, game.winner()), ((3, 1), Some(Side::Left)));
+        assert!(game.is_over());
+
+        let mut game = Match::new(
+            MatchRules {
+                serve: ServeRule::ToConceder,
+                ..MatchRules::default()
+            },
+            Side::Left,
+        );
+        game.point(Side::Left);
+        assert_eq!(game.serve_towards, Side::Right);
+        game.point(Side::Left);
+        assert_eq!(game.serve_towards, Side::Right);
+    }
+}
@@ -1,6 +1,11 @@
 use ggez::GameResult;
-use projekt::game::pong::pong;
+use projekt::{arguments::parse_local, controller::local_controllers, game::pong::pong};
 
 fn main() -> GameResult {
-    pong()
+    let (args, config) = parse_local();
+    let (left, right) = local_controllers(
+        (args.left, args.left_difficulty),
+        (args.right, args.right_difficulty),
+    );
+    pong(config, left, right)
 }